@@ -0,0 +1,112 @@
+// Frame times for the DamagedHelmet at 800x800, with the SIMD coverage path and without it.
+// Only coverage and the depth test are vectorized, shading runs the same scalar code in both.
+// The depth only pass (what shadow maps render) shows the rasterizer itself, shaded frames
+// spend most of their time in the fragment shaders.
+// cargo bench --bench raster [-- path/to/model.gltf]
+
+use glam::{Mat4, Quat, Vec3};
+use ruster::coverage::{set_simd_enabled, simd_available};
+use ruster::shadow::ShadowCasting;
+use ruster::{
+    Framebuffer, Light, Lighting, MaterialTextures, RendererCamera, Scene, Shading, ShadowMaps, Texture, Transform, View, render_scene,
+    render_scene_with,
+};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+// Not in the repository, only its textures are.
+const HELMET: &str = "assets/DamagedHelmet.gltf";
+const SIZE: usize = 800;
+const WARMUP: usize = 3;
+const FRAMES: usize = 20;
+
+fn median_frame(render: impl Fn(&Framebuffer)) -> Duration {
+    let framebuffer = Framebuffer::new(SIZE, SIZE);
+    let mut times: Vec<Duration> = (0..WARMUP + FRAMES)
+        .map(|_| {
+            let start = Instant::now();
+            framebuffer.clear(0);
+            render(&framebuffer);
+            start.elapsed()
+        })
+        .skip(WARMUP)
+        .collect();
+    times.sort();
+    times[FRAMES / 2]
+}
+
+// Times `render` with the SIMD path off, then on.
+fn compare(name: &str, render: impl Fn(&Framebuffer)) {
+    set_simd_enabled(false);
+    let scalar = median_frame(&render);
+    set_simd_enabled(true);
+    let simd = median_frame(&render);
+    println!(
+        "{}: scalar {:.2} ms, simd {:.2} ms, {:.2}x",
+        name,
+        scalar.as_secs_f64() * 1e3,
+        simd.as_secs_f64() * 1e3,
+        scalar.as_secs_f64() / simd.as_secs_f64()
+    );
+}
+
+fn main() -> ExitCode {
+    // cargo passes --bench along, anything else is the model to load
+    let model = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(HELMET));
+    if model == Path::new(HELMET) && !model.exists() {
+        eprintln!(
+            "{} is missing: download DamagedHelmet.gltf and DamagedHelmet.bin from Models/DamagedHelmet/glTF \
+             of https://github.com/KhronosGroup/glTF-Sample-Assets into assets/, or pass another model after --",
+            HELMET
+        );
+        return ExitCode::FAILURE;
+    }
+    let scene = match Scene::load_gltf(&model) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", model.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let albedo = Texture::load(Path::new("assets/DamagedHelmet_albedo.jpg")).ok();
+    let fallback = MaterialTextures {
+        base_color: albedo.as_ref(),
+        ..Default::default()
+    };
+
+    let (eye, target) = (Vec3::new(0.0, 1.0, 3.0), Vec3::ZERO);
+    let look = Mat4::look_at_rh(eye, target, Vec3::Y).inverse();
+    let camera = RendererCamera {
+        aspect_ratio: 1.0,
+        transform: Transform::from_translation_rotation(eye, Quat::from_mat4(&look)),
+        ..Default::default()
+    };
+    let lights = [Light::directional(Vec3::new(-1.0, -1.0, -1.0), Vec3::ONE, 3.0)];
+    // kept across frames like the viewer does
+    let shadows = ShadowMaps::default();
+    let lighting = Lighting {
+        lights: &lights,
+        environment: None,
+        shadows: Some(&shadows),
+    };
+
+    if !simd_available() {
+        println!("no SIMD path on this cpu, both columns run the scalar one");
+    }
+    println!("{} at {}x{}, median of {} frames", model.display(), SIZE, SIZE, FRAMES);
+    println!("simd covers the coverage and depth tests only, shading is scalar in both");
+    compare("Depth only", |framebuffer| {
+        render_scene_with(&scene, &View::from(&camera), &Lighting::default(), &fallback, framebuffer, &ShadowCasting, false)
+    });
+    for shading in [Shading::Unlit, Shading::Pbr] {
+        compare(&format!("{:?}", shading), |framebuffer| {
+            render_scene(&scene, &camera, &lighting, &fallback, framebuffer, shading)
+        });
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,312 @@
+// Offline renderer: loads a glTF, renders one frame and writes it to disk.
+// No window or GPU needed, so it runs on CI machines.
+//
+// Usage:
+//   render <model.gltf> [--output out.png] [--width 800] [--height 800]
+//          [--camera x,y,z] [--target x,y,z] [--texture albedo.jpg] [--filter trilinear]
+//          [--metallic-roughness map.jpg] [--normal-map map.jpg] [--occlusion map.jpg] [--emissive map.jpg]
+//          [--shading unlit|blinn-phong|pbr|wireframe] [--wireframe] [--light x,y,z]... [--light-intensity 1]
+//          [--environment sky.hdr]
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use glam::{Mat4, Quat, Vec3};
+use ruster::framebuffer::Framebuffer;
+use ruster::utilities::*;
+use ruster::{Environment, Filter, Light, Lighting, MaterialTextures, RendererCamera, Scene, Shading, Texture, Transform, render_scene};
+
+struct Args {
+    model: PathBuf,
+    output: PathBuf,
+    width: usize,
+    height: usize,
+    camera: Vec3,
+    target: Vec3,
+    texture: Option<PathBuf>,
+    metallic_roughness: Option<PathBuf>,
+    normal_map: Option<PathBuf>,
+    occlusion: Option<PathBuf>,
+    emissive: Option<PathBuf>,
+    filter: Filter,
+    // unlit without lights or an environment and pbr with them, unless asked otherwise
+    shading: Option<Shading>,
+    // directions the lights shine in
+    lights: Vec<Vec3>,
+    light_intensity: f32,
+    // equirectangular panorama for ambient light and the background
+    environment: Option<PathBuf>,
+}
+
+const USAGE: &str = "usage: render <model.gltf> [--output out.png|out.ppm] [--width N] [--height N] \
+[--camera x,y,z] [--target x,y,z] [--texture path] [--filter nearest|bilinear|trilinear|anisotropic] \
+[--metallic-roughness path] [--normal-map path] [--occlusion path] [--emissive path] \
+[--shading unlit|blinn-phong|pbr|wireframe] [--wireframe] [--light x,y,z]... [--light-intensity N] \
+[--environment path.hdr]";
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+    let parts: Vec<f32> = value
+        .split(',')
+        .map(|p| p.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid vector '{}': {}", value, e))?;
+    match parts.as_slice() {
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("expected x,y,z but got '{}'", value)),
+    }
+}
+
+fn parse_filter(value: &str) -> Result<Filter, String> {
+    match value {
+        "nearest" => Ok(Filter::Nearest),
+        "bilinear" => Ok(Filter::Bilinear),
+        "trilinear" => Ok(Filter::Trilinear),
+        "anisotropic" => Ok(Filter::Anisotropic),
+        _ => Err(format!("unknown filter '{}'", value)),
+    }
+}
+
+fn parse_shading(value: &str) -> Result<Shading, String> {
+    match value {
+        "unlit" => Ok(Shading::Unlit),
+        "blinn-phong" => Ok(Shading::BlinnPhong),
+        "pbr" => Ok(Shading::Pbr),
+        "wireframe" => Ok(Shading::Wireframe),
+        _ => Err(format!("unknown shading '{}'", value)),
+    }
+}
+
+// `args` without the program name
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut model = None;
+    let mut parsed = Args {
+        model: PathBuf::new(),
+        output: PathBuf::from("render.png"),
+        width: 800,
+        height: 800,
+        camera: Vec3::new(0.0, 0.0, 8.0),
+        target: Vec3::ZERO,
+        texture: None,
+        metallic_roughness: None,
+        normal_map: None,
+        occlusion: None,
+        emissive: None,
+        filter: Filter::default(),
+        shading: None,
+        lights: Vec::new(),
+        light_intensity: 1.0,
+        environment: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {}", name));
+        match arg.as_str() {
+            "--output" | "-o" => parsed.output = PathBuf::from(value(&arg)?),
+            "--width" => parsed.width = value(&arg)?.parse().map_err(|e| format!("--width: {}", e))?,
+            "--height" => parsed.height = value(&arg)?.parse().map_err(|e| format!("--height: {}", e))?,
+            "--camera" => parsed.camera = parse_vec3(&value(&arg)?)?,
+            "--target" => parsed.target = parse_vec3(&value(&arg)?)?,
+            "--texture" => parsed.texture = Some(PathBuf::from(value(&arg)?)),
+            "--filter" => parsed.filter = parse_filter(&value(&arg)?)?,
+            "--metallic-roughness" => parsed.metallic_roughness = Some(PathBuf::from(value(&arg)?)),
+            "--normal-map" => parsed.normal_map = Some(PathBuf::from(value(&arg)?)),
+            "--occlusion" => parsed.occlusion = Some(PathBuf::from(value(&arg)?)),
+            "--emissive" => parsed.emissive = Some(PathBuf::from(value(&arg)?)),
+            "--shading" => parsed.shading = Some(parse_shading(&value(&arg)?)?),
+            "--wireframe" => parsed.shading = Some(Shading::Wireframe),
+            "--light" => parsed.lights.push(parse_vec3(&value(&arg)?)?),
+            "--environment" => parsed.environment = Some(PathBuf::from(value(&arg)?)),
+            "--light-intensity" => {
+                parsed.light_intensity = value(&arg)?.parse().map_err(|e| format!("--light-intensity: {}", e))?
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}\n{}", arg, USAGE)),
+            _ => model = Some(PathBuf::from(arg)),
+        }
+    }
+
+    parsed.model = model.ok_or(USAGE.to_string())?;
+    if parsed.width == 0 || parsed.height == 0 {
+        return Err("resolution must be non zero".to_string());
+    }
+    Ok(parsed)
+}
+
+// Rotation pointing the camera at the target: the inverse of a view matrix is the camera's world transform.
+// Y can't be the up vector when looking straight up or down, Z takes over there.
+fn look_at(camera: Vec3, target: Vec3) -> Quat {
+    let direction = (target - camera).normalize_or_zero();
+    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    Quat::from_mat4(&Mat4::look_at_rh(camera, target, up).inverse())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut scene = match Scene::load_gltf(&args.model) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", args.model.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let environment = match args.environment.as_deref().map(Environment::load).transpose() {
+        Ok(environment) => environment,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", args.environment.as_ref().unwrap().display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // maps given on the command line stand in for the ones the materials don't have
+    let map_paths = [&args.texture, &args.metallic_roughness, &args.normal_map, &args.occlusion, &args.emissive];
+    let mut maps = Vec::with_capacity(map_paths.len());
+    for path in map_paths {
+        match path.as_deref().map(Texture::load).transpose() {
+            Ok(texture) => maps.push(texture),
+            Err(e) => {
+                eprintln!("failed to load {}: {}", path.as_ref().unwrap().display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    for texture in scene.textures.iter_mut().chain(maps.iter_mut().flatten()) {
+        texture.sampler.filter = args.filter;
+    }
+    let fallback = MaterialTextures {
+        base_color: maps[0].as_ref(),
+        metallic_roughness: maps[1].as_ref(),
+        normal: maps[2].as_ref(),
+        occlusion: maps[3].as_ref(),
+        emissive: maps[4].as_ref(),
+    };
+
+    let camera = RendererCamera {
+        aspect_ratio: args.width as f32 / args.height as f32,
+        transform: Transform::from_translation_rotation(args.camera, look_at(args.camera, args.target)),
+        ..Default::default()
+    };
+
+    // opaque black, a zero clear would leave the background transparent in the PNG
+    let framebuffer = Framebuffer::new(args.width, args.height);
+    framebuffer.clear(to_argb(255, 0, 0, 0));
+    let lights: Vec<Light> = args
+        .lights
+        .iter()
+        .map(|direction| Light::directional(*direction, Vec3::ONE, args.light_intensity))
+        .collect();
+    let lighting = Lighting {
+        lights: &lights,
+        environment: environment.as_ref(),
+        // a single frame, nothing to keep the shadow maps for
+        shadows: None,
+    };
+    let lit = !lights.is_empty() || environment.is_some();
+    let shading = args.shading.unwrap_or(if lit { Shading::Pbr } else { Shading::Unlit });
+    render_scene(&scene, &camera, &lighting, &fallback, &framebuffer, shading);
+
+    let result = match args.output.extension().and_then(|e| e.to_str()) {
+        Some("ppm") => write_ppm(&framebuffer.buffer, framebuffer.width, framebuffer.height, &args.output),
+        _ => write_png(&framebuffer.buffer, framebuffer.width, framebuffer.height, &args.output),
+    };
+
+    match result {
+        Ok(()) => {
+            println!("wrote {}", args.output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to write {}: {}", args.output.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).err().expect("arguments should be rejected")
+    }
+
+    #[test]
+    fn only_the_model_is_required() {
+        let args = parse(&["helmet.gltf"]).unwrap();
+        assert_eq!(args.model, PathBuf::from("helmet.gltf"));
+        assert_eq!(args.output, PathBuf::from("render.png"));
+        assert_eq!((args.width, args.height), (800, 800));
+        assert_eq!((args.camera, args.target), (Vec3::new(0.0, 0.0, 8.0), Vec3::ZERO));
+        assert!(args.texture.is_none());
+        assert!(args.metallic_roughness.is_none() && args.normal_map.is_none());
+        assert!(args.occlusion.is_none() && args.emissive.is_none());
+        assert_eq!(args.filter, Filter::Trilinear);
+        // picked from the lights later on
+        assert_eq!(args.shading, None);
+        assert!(args.lights.is_empty());
+        assert_eq!(args.light_intensity, 1.0);
+        assert!(args.environment.is_none());
+    }
+
+    #[test]
+    fn options_override_the_defaults() {
+        let args = parse(&[
+            "--width", "256", "-o", "thumb.ppm", "helmet.gltf", "--height", "128", "--camera", "1, 2,3",
+            "--target", "0,1,0", "--texture", "albedo.jpg", "--filter", "nearest", "--normal-map", "normal.png", "--emissive", "glow.png", "--shading", "blinn-phong",
+            "--light", "0,-1,0", "--light", "1,0,0", "--light-intensity", "2.5",
+            "--environment", "sky.hdr",
+        ])
+        .unwrap();
+        assert_eq!(args.model, PathBuf::from("helmet.gltf"));
+        assert_eq!(args.output, PathBuf::from("thumb.ppm"));
+        assert_eq!((args.width, args.height), (256, 128));
+        assert_eq!((args.camera, args.target), (Vec3::new(1.0, 2.0, 3.0), Vec3::Y));
+        assert_eq!(args.texture, Some(PathBuf::from("albedo.jpg")));
+        assert_eq!(args.filter, Filter::Nearest);
+        assert_eq!((args.normal_map, args.emissive), (Some(PathBuf::from("normal.png")), Some(PathBuf::from("glow.png"))));
+        assert_eq!(args.shading, Some(Shading::BlinnPhong));
+        // lights add up instead of replacing each other
+        assert_eq!(args.lights, [-Vec3::Y, Vec3::X]);
+        assert_eq!(args.light_intensity, 2.5);
+        assert_eq!(args.environment, Some(PathBuf::from("sky.hdr")));
+        // the old flag is a shorthand for the shading
+        assert_eq!(parse(&["helmet.gltf", "--wireframe"]).unwrap().shading, Some(Shading::Wireframe));
+        assert_eq!(parse(&["helmet.gltf", "--filter", "anisotropic"]).unwrap().filter, Filter::Anisotropic);
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert_eq!(error(&[]), USAGE);
+        assert_eq!(error(&["--help"]), USAGE);
+        assert!(error(&["helmet.gltf", "--fov", "90"]).starts_with("unknown option --fov"));
+        assert_eq!(error(&["helmet.gltf", "--width"]), "missing value for --width");
+        assert!(error(&["helmet.gltf", "--width", "wide"]).starts_with("--width:"));
+        assert_eq!(error(&["helmet.gltf", "--height", "0"]), "resolution must be non zero");
+        assert_eq!(error(&["helmet.gltf", "--camera", "1,2"]), "expected x,y,z but got '1,2'");
+        assert!(error(&["helmet.gltf", "--target", "1,y,3"]).starts_with("invalid vector '1,y,3'"));
+        assert_eq!(error(&["helmet.gltf", "--filter", "cubic"]), "unknown filter 'cubic'");
+        assert_eq!(error(&["helmet.gltf", "--shading", "toon"]), "unknown shading 'toon'");
+        assert!(error(&["helmet.gltf", "--light-intensity", "bright"]).starts_with("--light-intensity:"));
+    }
+
+    #[test]
+    fn cameras_look_at_the_target_from_any_side() {
+        for camera in [Vec3::new(0.0, 0.0, 8.0), Vec3::new(3.0, 2.0, -1.0), Vec3::new(0.0, 8.0, 0.0), Vec3::new(0.0, -8.0, 0.0)] {
+            let rotation = look_at(camera, Vec3::ZERO);
+            assert!(rotation.is_finite(), "{}", camera);
+            let forward = Transform::from_translation_rotation(camera, rotation).forward();
+            assert!(forward.abs_diff_eq(-camera.normalize(), 1e-5), "{} looks along {}", camera, forward);
+        }
+    }
+}
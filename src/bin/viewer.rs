@@ -0,0 +1,343 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::window::WindowResized;
+use bevy_egui::{EguiContexts, EguiPlugin, EguiPrimaryContextPass, egui};
+use glam::Vec3 as GVec3;
+
+#[derive(Resource)]
+struct RasterizerState {
+    framebuffer: Framebuffer,
+    scene: RasterScene,
+    camera: RendererCamera,
+    shading: Shading,
+    filter: Filter,
+    lights: Vec<Light>,
+    environment: Option<Environment>,
+    // one per light, refitted and redrawn every frame
+    shadows: ShadowMaps,
+    // instance picked in the outliner
+    selected: Option<usize>,
+}
+
+// This is attached to an entity so I can acces the buffer anytime.
+#[derive(Resource)]
+struct FramebufferImageHandle(Handle<Image>);
+
+use ruster::camera::*;
+use ruster::framebuffer::*;
+use ruster::geometry::*;
+use ruster::environment::Environment;
+use ruster::light::{Light, LightKind, Lighting};
+use ruster::sampler::Filter;
+use ruster::material::MaterialTextures;
+use ruster::scene::{Scene as RasterScene, Shading, render_scene};
+use ruster::shadow::ShadowMaps;
+use ruster::utilities::*;
+use ruster::window::*;
+
+fn startup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    // Resources (scene with its materials and textures, camera)
+    let camera = RendererCamera::default();
+
+    // a broken asset is reported and leaves the viewer running with an empty scene
+    let scene_path = Path::new("assets/DamagedHelmet.gltf");
+    let scene = RasterScene::load_gltf(scene_path).unwrap_or_else(|e| {
+        eprintln!("failed to load {}: {}", scene_path.display(), e);
+        RasterScene::new()
+    });
+
+    // optional sky for ambient light and the background
+    let environment_path = Path::new("assets/environment.hdr");
+    let environment = environment_path
+        .exists()
+        .then(|| Environment::load(environment_path))
+        .and_then(|result| result.inspect_err(|e| eprintln!("failed to load {}: {}", environment_path.display(), e)).ok());
+
+    let shading = Shading::Wireframe;
+
+    // Framebuffer to rasterize into, resized along with the window
+    let framebuffer = Framebuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+
+    // Create a Bevy image (GPU texture) from your framebuffer
+    // This is where format conversion happens
+    let image = Image::new_fill(
+        Extent3d {
+            width: SCREEN_WIDTH as u32,
+            height: SCREEN_HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255], // Initial black color
+        TextureFormat::Rgba8UnormSrgb,
+        Default::default(),
+    );
+
+    // Add the image to Bevy's asset system and get a handle
+    let image_handle = images.add(image);
+
+    commands.spawn((
+        Sprite {
+            image: image_handle.clone(),
+            custom_size: Some(Vec2::new(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32)),
+            ..default()
+        },
+        Transform::from_xyz(0.0, 0.0, 0.0),
+    ));
+
+    // Spawn a camera so the sprite gets rendered
+    commands.spawn(Camera2d);
+
+    // Store the rasterizer state and image handle as resources
+    commands.insert_resource(RasterizerState {
+        framebuffer,
+        scene,
+        camera,
+        shading,
+        filter: Filter::default(),
+        lights: vec![Light::directional(GVec3::new(-1.0, -1.0, -1.0), GVec3::ONE, 1.0)],
+        environment,
+        shadows: ShadowMaps::default(),
+        selected: None,
+    });
+    commands.insert_resource(FramebufferImageHandle(image_handle));
+}
+
+fn update() {}
+
+// Keeps the framebuffer, the backing image and the sprite showing it at window resolution.
+fn resize_framebuffer(
+    mut resize_reader: MessageReader<WindowResized>,
+    mut images: ResMut<Assets<Image>>,
+    image_handle: Res<FramebufferImageHandle>,
+    mut sprites: Query<&mut Sprite>,
+    mut state: ResMut<RasterizerState>,
+) {
+    // only the latest size matters when several resizes land in one frame
+    let Some(resized) = resize_reader.read().last() else {
+        return;
+    };
+
+    let width = (resized.width.round() as usize).max(1);
+    let height = (resized.height.round() as usize).max(1);
+
+    state.framebuffer.resize(width, height);
+    state.camera.aspect_ratio = state.framebuffer.aspect_ratio();
+
+    if let Some(image) = images.get_mut(&image_handle.0) {
+        image.resize(Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        });
+    }
+
+    for mut sprite in sprites.iter_mut() {
+        if sprite.image == image_handle.0 {
+            sprite.custom_size = Some(Vec2::new(width as f32, height as f32));
+        }
+    }
+}
+
+fn render(
+    mut images: ResMut<Assets<Image>>,
+    image_handle: Res<FramebufferImageHandle>,
+    mut state: ResMut<RasterizerState>,
+) {
+    let RasterizerState {
+        framebuffer,
+        scene,
+        camera,
+        shading,
+        filter,
+        lights,
+        environment,
+        shadows,
+        ..
+    } = &mut *state;
+
+    for texture in scene.textures.iter_mut() {
+        texture.sampler.filter = *filter;
+    }
+
+    // Clear color and depth
+    framebuffer.clear(0);
+
+    render_scene(
+        &*scene,
+        camera,
+        &Lighting {
+            lights,
+            environment: environment.as_ref(),
+            shadows: Some(&*shadows),
+        },
+        &MaterialTextures::default(),
+        framebuffer,
+        *shading
+    );
+
+    // Credit: Codex 5.2 + utility to convert
+    // Get the Bevy image and update its data
+    if let Some(image) = images.get_mut(&image_handle.0) {
+        // Convert your ARGB u32 buffer to RGBA byte buffer
+        if let Some(data) = image.data.as_mut() {
+            convert_framebuffer_to_image(&framebuffer.buffer, data);
+        }
+    }
+}
+
+fn render_egui(mut contexts: EguiContexts, mut state: ResMut<RasterizerState>) {
+    if let Ok(ctx) = contexts.ctx_mut() {
+        // Credit: Codex 5.2
+        egui::Window::new("Outliner").show(ctx, |ui| {
+            let RasterizerState { scene, selected, .. } = &mut *state;
+
+            egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                for (i, instance) in scene.instances.iter().enumerate() {
+                    if ui.selectable_label(*selected == Some(i), format!("{} {}", i, instance.name)).clicked() {
+                        *selected = Some(i);
+                    }
+                }
+            });
+
+            // the list can shrink when another scene is loaded
+            let Some(instance) = selected.and_then(|i| scene.instances.get_mut(i)) else {
+                return;
+            };
+            let transform = &mut instance.transform;
+
+            ui.separator();
+            ui.label("Translation");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut transform.translation.x).speed(0.05).prefix("x "));
+                ui.add(egui::DragValue::new(&mut transform.translation.y).speed(0.05).prefix("y "));
+                ui.add(egui::DragValue::new(&mut transform.translation.z).speed(0.05).prefix("z "));
+            });
+
+            // edited as euler angles, stored as a quaternion
+            ui.label("Rotation (deg)");
+            let (x, y, z) = transform.rotation.to_euler(glam::EulerRot::XYZ);
+            let mut rotation_deg = GVec3::new(x, y, z).map(f32::to_degrees);
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut rotation_deg.x).speed(1.0).prefix("x "));
+                ui.add(egui::DragValue::new(&mut rotation_deg.y).speed(1.0).prefix("y "));
+                ui.add(egui::DragValue::new(&mut rotation_deg.z).speed(1.0).prefix("z "));
+            });
+            let rotation = rotation_deg.map(f32::to_radians);
+            let rotation = glam::Quat::from_euler(glam::EulerRot::XYZ, rotation.x, rotation.y, rotation.z);
+            // writing back an unchanged rotation would let the euler round trip drift it
+            if rotation.angle_between(transform.rotation) > 1e-5 {
+                transform.rotation = rotation;
+            }
+
+            ui.label("Scale");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut transform.scale.x).speed(0.01).range(0.01..=100.0).prefix("x "));
+                ui.add(egui::DragValue::new(&mut transform.scale.y).speed(0.01).range(0.01..=100.0).prefix("y "));
+                ui.add(egui::DragValue::new(&mut transform.scale.z).speed(0.01).range(0.01..=100.0).prefix("z "));
+            });
+        });
+
+        egui::Window::new("Rendering").show(ctx, |ui| {
+            egui::ComboBox::from_label("Shading")
+                .selected_text(format!("{:?}", state.shading))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.shading, Shading::Wireframe, "Wireframe");
+                    ui.selectable_value(&mut state.shading, Shading::Unlit, "Unlit");
+                    ui.selectable_value(&mut state.shading, Shading::BlinnPhong, "Blinn-Phong");
+                    ui.selectable_value(&mut state.shading, Shading::Pbr, "PBR");
+                });
+
+            egui::ComboBox::from_label("Texture filter")
+                .selected_text(format!("{:?}", state.filter))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.filter, Filter::Nearest, "Nearest");
+                    ui.selectable_value(&mut state.filter, Filter::Bilinear, "Bilinear");
+                    ui.selectable_value(&mut state.filter, Filter::Trilinear, "Trilinear");
+                    ui.selectable_value(&mut state.filter, Filter::Anisotropic, "Anisotropic");
+                });
+        });
+
+        egui::Window::new("Lights").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Add directional").clicked() {
+                    state.lights.push(Light::directional(-GVec3::Y, GVec3::ONE, 1.0));
+                }
+                if ui.button("Add point").clicked() {
+                    state.lights.push(Light::point(GVec3::new(0.0, 2.0, 2.0), GVec3::ONE, 10.0, 20.0));
+                }
+                if ui.button("Add spot").clicked() {
+                    let cone = 30f32.to_radians();
+                    state.lights.push(Light::spot(GVec3::new(0.0, 3.0, 3.0), GVec3::new(0.0, -1.0, -1.0), GVec3::ONE, 20.0, 20.0, cone * 0.5, cone));
+                }
+            });
+
+            // an empty list renders unlit
+            let mut removed = None;
+            for (i, light) in state.lights.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} {:?}", i, light.kind));
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+
+                if light.kind != LightKind::Directional {
+                    ui.horizontal(|ui| {
+                        ui.label("Position");
+                        ui.add(egui::DragValue::new(&mut light.position.x).speed(0.1));
+                        ui.add(egui::DragValue::new(&mut light.position.y).speed(0.1));
+                        ui.add(egui::DragValue::new(&mut light.position.z).speed(0.1));
+                    });
+                    ui.add(egui::Slider::new(&mut light.range, 0.1..=100.0).text("range"));
+                }
+                if light.kind != LightKind::Point {
+                    ui.horizontal(|ui| {
+                        ui.label("Direction");
+                        ui.add(egui::DragValue::new(&mut light.direction.x).speed(0.05));
+                        ui.add(egui::DragValue::new(&mut light.direction.y).speed(0.05));
+                        ui.add(egui::DragValue::new(&mut light.direction.z).speed(0.05));
+                    });
+                }
+                if light.kind != LightKind::Point {
+                    ui.checkbox(&mut light.cast_shadows, "Cast shadows");
+                }
+                if let LightKind::Spot { inner_cone, outer_cone } = &mut light.kind {
+                    ui.add(egui::Slider::new(outer_cone, 0.0..=std::f32::consts::FRAC_PI_2).text("outer cone"));
+                    ui.add(egui::Slider::new(inner_cone, 0.0..=*outer_cone).text("inner cone"));
+                }
+
+                let mut color = light.color.to_array();
+                ui.horizontal(|ui| {
+                    ui.label("Color");
+                    ui.color_edit_button_rgb(&mut color);
+                });
+                light.color = GVec3::from_array(color);
+                ui.add(egui::Slider::new(&mut light.intensity, 0.0..=50.0).text("intensity"));
+            }
+            if let Some(i) = removed {
+                state.lights.remove(i);
+            }
+        });
+    }
+}
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resolution: bevy::window::WindowResolution::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32),
+                title: "Rasterizer".to_string(),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(EguiPlugin::default())
+        .add_systems(Startup, startup)
+        .add_systems(Update, update)
+        .add_systems(Update, (resize_framebuffer, render).chain())
+        .add_systems(EguiPrimaryContextPass, render_egui)
+        .run();
+}
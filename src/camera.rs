@@ -1,7 +1,7 @@
 use crate::transform::Transform;
 
 use crate::window::*;
-use glam::Mat4;
+use glam::{Mat4, Vec3};
 pub struct RendererCamera {
     pub frustum_near: f32,
     pub frustum_far: f32,
@@ -40,3 +40,20 @@ impl RendererCamera {
         )
     }
 }
+
+// Where a pass looks from: a camera for the frame, a light for its shadow map.
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    pub view_projection: Mat4,
+    // eye position in world space, for view dependent shading
+    pub position: Vec3,
+}
+
+impl From<&RendererCamera> for View {
+    fn from(camera: &RendererCamera) -> View {
+        View {
+            view_projection: camera.projection() * camera.view(),
+            position: camera.transform.translation,
+        }
+    }
+}
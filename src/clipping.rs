@@ -0,0 +1,118 @@
+use crate::geometry::Vertex;
+use crate::shader::{Varyings, VertexOutput};
+use glam::Vec4;
+use std::ops::{Add, Mul, Sub};
+
+// Anything with a clip space position that can be interpolated along an edge.
+pub trait ClipVertex: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {
+    fn clip_position(&self) -> Vec4;
+}
+
+impl ClipVertex for Vertex {
+    fn clip_position(&self) -> Vec4 {
+        self.position
+    }
+}
+
+impl<V: Varyings> ClipVertex for VertexOutput<V> {
+    fn clip_position(&self) -> Vec4 {
+        self.position
+    }
+}
+
+// Clip space planes as 4D dot products, a point is inside when dot(plane, position) >= 0.
+// glam's perspective_rh maps depth to [0, 1], so near is z >= 0 and far is z <= w.
+const CLIP_PLANES: [Vec4; 6] = [
+    Vec4::new(0.0, 0.0, 1.0, 0.0),  // near:   z >= 0
+    Vec4::new(0.0, 0.0, -1.0, 1.0), // far:    z <= w
+    Vec4::new(1.0, 0.0, 0.0, 1.0),  // left:   x >= -w
+    Vec4::new(-1.0, 0.0, 0.0, 1.0), // right:  x <= w
+    Vec4::new(0.0, 1.0, 0.0, 1.0),  // bottom: y >= -w
+    Vec4::new(0.0, -1.0, 0.0, 1.0), // top:    y <= w
+];
+
+// A triangle clipped against 6 planes gains at most one vertex per plane.
+pub const MAX_CLIPPED_VERTICES: usize = 9;
+
+/// One bit per frustum plane the clip space position is outside of.
+/// A triangle whose outcodes are all 0 needs no clipping, one where they share a bit
+/// is entirely outside that plane.
+///
+/// ```
+/// use glam::vec4;
+/// use ruster::clipping::outcode;
+///
+/// assert_eq!(outcode(vec4(0.0, 0.0, 0.5, 1.0)), 0);
+/// assert_ne!(outcode(vec4(2.0, 0.0, 0.5, 1.0)), 0);
+/// ```
+pub fn outcode(position: Vec4) -> u8 {
+    CLIP_PLANES
+        .iter()
+        .enumerate()
+        .fold(0, |code, (i, plane)| if plane.dot(position) < 0.0 { code | 1 << i } else { code })
+}
+
+fn fully_inside<T: ClipVertex>(triangle: &[T; 3]) -> bool {
+    CLIP_PLANES
+        .iter()
+        .all(|plane| triangle.iter().all(|v| plane.dot(v.clip_position()) >= 0.0))
+}
+
+/// Sutherland-Hodgman clipping of a clip space triangle against the view frustum.
+/// Returns the clipped convex polygon, empty when the triangle is fully outside.
+/// New vertices are interpolated linearly in clip space, before perspective division.
+///
+/// ```
+/// use glam::{vec2, vec3, vec4};
+/// use ruster::Vertex;
+/// use ruster::clipping::clip_triangle;
+///
+/// let v = |x: f32, z: f32, w: f32| Vertex::new(vec4(x, 0.0, z, w), vec3(0.0, 0.0, 1.0), vec3(1.0, 1.0, 1.0), vec2(0.0, 0.0));
+/// // one vertex behind the camera (w < 0) gets cut away at the near plane
+/// let polygon = clip_triangle(&[v(0.0, 0.5, 1.0), v(0.5, 0.5, 1.0), v(0.0, -2.0, -1.0)]);
+/// assert_eq!(polygon.len(), 4);
+/// assert!(polygon.iter().all(|v| v.position.z >= 0.0 && v.position.w > 0.0));
+/// ```
+pub fn clip_triangle<T: ClipVertex>(triangle: &[T; 3]) -> Vec<T> {
+    let mut polygon: Vec<T> = Vec::with_capacity(MAX_CLIPPED_VERTICES);
+    polygon.extend_from_slice(triangle);
+
+    // common case, nothing to do
+    if fully_inside(triangle) {
+        return polygon;
+    }
+
+    let mut input: Vec<T> = Vec::with_capacity(MAX_CLIPPED_VERTICES);
+    for plane in CLIP_PLANES.iter() {
+        std::mem::swap(&mut polygon, &mut input);
+        polygon.clear();
+
+        for i in 0..input.len() {
+            let current = input[i];
+            let next = input[(i + 1) % input.len()];
+            let d_current = plane.dot(current.clip_position());
+            let d_next = plane.dot(next.clip_position());
+
+            if d_current >= 0.0 {
+                polygon.push(current);
+            }
+            // edge crosses the plane, emit the intersection
+            if (d_current >= 0.0) != (d_next >= 0.0) {
+                let t = d_current / (d_current - d_next);
+                polygon.push(current + (next - current) * t);
+            }
+        }
+
+        if polygon.len() < 3 {
+            polygon.clear();
+            break;
+        }
+    }
+
+    polygon
+}
+
+// Fan triangulation of a convex polygon, keeps the original winding.
+pub fn triangulate<T: Copy>(polygon: &[T]) -> impl Iterator<Item = [T; 3]> + '_ {
+    (1..polygon.len().saturating_sub(1)).map(move |i| [polygon[0], polygon[i], polygon[i + 1]])
+}
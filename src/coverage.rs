@@ -0,0 +1,337 @@
+use crate::framebuffer::unpack_depth;
+use crate::utilities::{SUBPIXEL_BITS, edge_function_fixed, is_top_left};
+use glam::{I64Vec2, Vec3};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Pixels tested together by the SIMD path. It only vectorizes coverage and the early depth test,
+// shading the pixels that pass stays scalar.
+pub const LANES: usize = 4;
+// Longest run of a row handed to the shading loop at once, a tile row in the binned renderer.
+pub const SPAN: usize = 64;
+
+static SIMD_ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Turns the SIMD path off, to compare against the scalar one. Both produce the same pixels.
+pub fn set_simd_enabled(enabled: bool) {
+    SIMD_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn simd_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("avx") {
+        return true;
+    }
+    false
+}
+
+// A pixel that is inside the triangle and in front of what the depth buffer held.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Covered {
+    // from the start of the span
+    pub x: usize,
+    pub bary: Vec3,
+    // view depth (clip w) and the clip z the depth buffer keys on
+    pub depth: f32,
+    pub z: f32,
+}
+
+// Triangle setup for the inner loop: the three edge functions are linear in the pixel position,
+// so they are evaluated once at the first pixel and stepped from there.
+pub struct EdgeSetup {
+    // edge values at the center of the first pixel of the current row
+    row: [i64; 3],
+    // change per pixel to the right and per row down
+    step_x: [i64; 3],
+    step_y: [i64; 3],
+    // 1 on edges that aren't top-left, see is_top_left
+    bias: [i64; 3],
+    rec_area: f32,
+    rec_w: [f32; 3],
+    z_over_w: [f32; 3],
+}
+
+impl EdgeSetup {
+    // `fixed` are the snapped screen positions, (x, y) the first pixel visited.
+    pub fn new(fixed: [I64Vec2; 3], rec_w: [f32; 3], z_over_w: [f32; 3], x: usize, y: usize) -> Self {
+        let pixel = 1i64 << SUBPIXEL_BITS;
+        let center = I64Vec2::new(x as i64 * pixel + pixel / 2, y as i64 * pixel + pixel / 2);
+        // edge i is the one opposite vertex i, like the barycentric weight it produces
+        let edges = [(fixed[1], fixed[2]), (fixed[2], fixed[0]), (fixed[0], fixed[1])];
+
+        Self {
+            row: edges.map(|(from, to)| edge_function_fixed(from, to, center)),
+            step_x: edges.map(|(from, to)| (to.y - from.y) * pixel),
+            step_y: edges.map(|(from, to)| (from.x - to.x) * pixel),
+            bias: edges.map(|(from, to)| !is_top_left(from, to) as i64),
+            rec_area: 1.0 / edge_function_fixed(fixed[0], fixed[1], fixed[2]) as f32,
+            rec_w,
+            z_over_w,
+        }
+    }
+
+    pub fn next_row(&mut self) {
+        for (row, step) in self.row.iter_mut().zip(self.step_y) {
+            *row += step;
+        }
+    }
+
+    // Perspective correct view depth and clip z at the barycentric coordinates.
+    // The SIMD path does the same operations in the same order, so both agree to the bit.
+    fn depth(&self, bary: Vec3) -> (f32, f32) {
+        // interpolated view depth (clip w), smaller is closer
+        let depth = 1.0 / (bary.x * self.rec_w[0] + bary.y * self.rec_w[1] + bary.z * self.rec_w[2]);
+        // The depth buffer keys on clip z instead: it grows with distance under perspective
+        // and orthographic projections alike, where w stays 1. Clipping keeps it >= 0,
+        // max folds -0.0 into 0.0 so the float bits keep sorting.
+        let z = ((bary.x * self.z_over_w[0] + bary.y * self.z_over_w[1] + bary.z * self.z_over_w[2]) * depth).max(0.0);
+        (depth, z)
+    }
+
+    // Tests the pixels of the current row starting `start` pixels in, one per entry of `depths`
+    // (at most SPAN), against the edges and the depth buffer. Survivors go to `out`, their count is returned.
+    // The depth test here is only an early out, the atomic fetch_min on write is what decides.
+    pub fn span(&self, start: usize, depths: &[AtomicU64], out: &mut [Covered; SPAN]) -> usize {
+        debug_assert!(depths.len() <= SPAN);
+
+        #[cfg(target_arch = "x86_64")]
+        if SIMD_ENABLED.load(Ordering::Relaxed) && std::arch::is_x86_feature_detected!("avx") {
+            // SAFETY: span_avx only needs avx, which the cpu was just checked to support
+            return unsafe { self.span_avx(start, depths, out) };
+        }
+        self.span_scalar(start, depths, out)
+    }
+
+    fn span_scalar(&self, start: usize, depths: &[AtomicU64], out: &mut [Covered; SPAN]) -> usize {
+        let mut w = [0, 1, 2].map(|i| self.row[i] + self.step_x[i] * start as i64);
+        let mut count = 0;
+
+        for (x, stored) in depths.iter().enumerate() {
+            if w[0] >= self.bias[0] && w[1] >= self.bias[1] && w[2] >= self.bias[2] {
+                let bary = Vec3::new(w[0] as f32, w[1] as f32, w[2] as f32) * self.rec_area;
+                let (depth, z) = self.depth(bary);
+                if z < unpack_depth(stored.load(Ordering::Relaxed)) {
+                    out[count] = Covered { x, bary, depth, z };
+                    count += 1;
+                }
+            }
+            for (w, step) in w.iter_mut().zip(self.step_x) {
+                *w += step;
+            }
+        }
+        count
+    }
+
+    // LANES pixels at a time. Edge values go in f64 lanes: they are integers well below 2^53,
+    // so adding and comparing them stays exact and the fill rule holds like in the scalar path.
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    fn span_avx(&self, start: usize, depths: &[AtomicU64], out: &mut [Covered; SPAN]) -> usize {
+        use std::arch::x86_64::*;
+
+        let lane = _mm256_set_pd(3.0, 2.0, 1.0, 0.0);
+        let mut w = [0, 1, 2].map(|i| {
+            let first = (self.row[i] + self.step_x[i] * start as i64) as f64;
+            _mm256_add_pd(_mm256_set1_pd(first), _mm256_mul_pd(lane, _mm256_set1_pd(self.step_x[i] as f64)))
+        });
+        let step = [0, 1, 2].map(|i| _mm256_set1_pd((self.step_x[i] * LANES as i64) as f64));
+        let bias = [0, 1, 2].map(|i| _mm256_set1_pd(self.bias[i] as f64));
+
+        let rec_area = _mm_set1_ps(self.rec_area);
+        let rec_w = self.rec_w.map(|v| _mm_set1_ps(v));
+        let z_over_w = self.z_over_w.map(|v| _mm_set1_ps(v));
+
+        let mut count = 0;
+        for (chunk, stored) in depths.chunks(LANES).enumerate() {
+            let inside = _mm256_and_pd(
+                _mm256_and_pd(_mm256_cmp_pd::<_CMP_GE_OQ>(w[0], bias[0]), _mm256_cmp_pd::<_CMP_GE_OQ>(w[1], bias[1])),
+                _mm256_cmp_pd::<_CMP_GE_OQ>(w[2], bias[2]),
+            );
+            // the last chunk of a span can be partial
+            let mut mask = _mm256_movemask_pd(inside) as u32 & ((1 << stored.len()) - 1);
+
+            if mask != 0 {
+                let b = [0, 1, 2].map(|i| _mm_mul_ps(_mm256_cvtpd_ps(w[i]), rec_area));
+                let sum = |v: &[__m128; 3]| {
+                    _mm_add_ps(_mm_add_ps(_mm_mul_ps(b[0], v[0]), _mm_mul_ps(b[1], v[1])), _mm_mul_ps(b[2], v[2]))
+                };
+                let depth = _mm_div_ps(_mm_set1_ps(1.0), sum(&rec_w));
+                let z = _mm_max_ps(_mm_mul_ps(sum(&z_over_w), depth), _mm_setzero_ps());
+
+                let mut buffer = [f32::INFINITY; LANES];
+                for (i, packed) in stored.iter().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        buffer[i] = unpack_depth(packed.load(Ordering::Relaxed));
+                    }
+                }
+                let stored_depth = _mm_set_ps(buffer[3], buffer[2], buffer[1], buffer[0]);
+                mask &= _mm_movemask_ps(_mm_cmplt_ps(z, stored_depth)) as u32;
+
+                if mask != 0 {
+                    let mut lanes = [[0.0f32; LANES]; 5];
+                    for (dst, src) in lanes.iter_mut().zip([b[0], b[1], b[2], depth, z]) {
+                        // SAFETY: dst holds exactly LANES floats, storeu has no alignment requirement
+                        unsafe { _mm_storeu_ps(dst.as_mut_ptr(), src) };
+                    }
+                    while mask != 0 {
+                        let i = mask.trailing_zeros() as usize;
+                        out[count] = Covered {
+                            x: chunk * LANES + i,
+                            bary: Vec3::new(lanes[0][i], lanes[1][i], lanes[2][i]),
+                            depth: lanes[3][i],
+                            z: lanes[4][i],
+                        };
+                        count += 1;
+                        mask &= mask - 1;
+                    }
+                }
+            }
+
+            for (w, step) in w.iter_mut().zip(step) {
+                *w = _mm256_add_pd(*w, step);
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{CLEAR_DEPTH, pack_depth_color};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Mutex;
+
+    const WIDTH: usize = 70;
+    const HEIGHT: usize = 20;
+
+    // set_simd_enabled is global, tests switching it take turns
+    static SIMD_SWITCH: Mutex<()> = Mutex::new(());
+
+    fn pixels(points: [(f32, f32); 3]) -> [I64Vec2; 3] {
+        points.map(|(x, y)| I64Vec2::new((x * 256.0) as i64, (y * 256.0) as i64))
+    }
+
+    fn triangles() -> Vec<[I64Vec2; 3]> {
+        let mut triangles = vec![
+            // edges running through pixel centers: vertical, horizontal and diagonal
+            pixels([(2.5, 1.5), (2.5, 17.5), (40.5, 1.5)]),
+            pixels([(0.5, 0.5), (15.5, 15.5), (60.5, 0.5)]),
+            pixels([(3.5, 3.5), (3.5, 18.5), (18.5, 18.5)]),
+            // reaching past the right end of the rows
+            pixels([(50.0, 2.0), (45.0, 19.0), (90.0, 10.0)]),
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..8 {
+            let mut fixed = [0; 3].map(|_| {
+                I64Vec2::new(rng.random_range(-2000..(WIDTH as i64 + 8) * 256), rng.random_range(-2000..(HEIGHT as i64 + 8) * 256))
+            });
+            if edge_function_fixed(fixed[0], fixed[1], fixed[2]) < 0 {
+                fixed.swap(1, 2);
+            }
+            if edge_function_fixed(fixed[0], fixed[1], fixed[2]) > 0 {
+                triangles.push(fixed);
+            }
+        }
+        triangles
+    }
+
+    fn setup(fixed: [I64Vec2; 3]) -> EdgeSetup {
+        EdgeSetup::new(fixed, [1.0, 0.5, 0.25], [0.2, 0.6, 0.9], 0, 0)
+    }
+
+    // Some pixels cleared, others already holding something nearer or farther.
+    fn depth_buffer() -> Vec<AtomicU64> {
+        let mut rng = StdRng::seed_from_u64(11);
+        (0..WIDTH * HEIGHT)
+            .map(|_| match rng.random_range(0..3) {
+                0 => AtomicU64::new(CLEAR_DEPTH),
+                _ => AtomicU64::new(pack_depth_color(rng.random_range(0.0..1.0), 0)),
+            })
+            .collect()
+    }
+
+    // Everything span reports, with the floats as bits so both paths have to agree exactly.
+    fn run_span(edges: &EdgeSetup, start: usize, depths: &[AtomicU64], simd: bool) -> Vec<(usize, [u32; 5])> {
+        set_simd_enabled(simd);
+        let mut out = [Covered::default(); SPAN];
+        let count = edges.span(start, depths, &mut out);
+        out[..count]
+            .iter()
+            .map(|c| (c.x, [c.bary.x, c.bary.y, c.bary.z, c.depth, c.z].map(f32::to_bits)))
+            .collect()
+    }
+
+    #[test]
+    fn simd_and_scalar_spans_agree() {
+        let _switch = SIMD_SWITCH.lock().unwrap_or_else(|e| e.into_inner());
+        let depths = depth_buffer();
+
+        for fixed in triangles() {
+            let mut edges = setup(fixed);
+            for y in 0..HEIGHT {
+                let row = &depths[y * WIDTH..(y + 1) * WIDTH];
+                // starts inside and at the end of a group of LANES, down to the last pixel of the row
+                for start in [0, 1, 2, 3, 5, 17, WIDTH - 6, WIDTH - 5, WIDTH - 3, WIDTH - 2, WIDTH - 1] {
+                    // lengths ending anywhere in a group, up to a full SPAN
+                    for len in 1..=SPAN.min(WIDTH - start) {
+                        let span = &row[start..start + len];
+                        let scalar = run_span(&edges, start, span, false);
+                        let simd = run_span(&edges, start, span, true);
+                        assert_eq!(scalar, simd, "triangle {fixed:?} row {y} start {start} len {len}");
+                        assert!(scalar.iter().all(|&(x, _)| x < len));
+                    }
+                }
+                edges.next_row();
+            }
+        }
+        set_simd_enabled(true);
+    }
+
+    // Whole rows in SPAN pieces like the rasterizer walks them, writing depth with fetch_min.
+    fn raster_depths(fixed: [I64Vec2; 3], simd: bool) -> Vec<u64> {
+        let depths = depth_buffer();
+        let mut edges = setup(fixed);
+        for y in 0..HEIGHT {
+            let row = &depths[y * WIDTH..(y + 1) * WIDTH];
+            for start in (0..WIDTH).step_by(SPAN) {
+                let span = &row[start..(start + SPAN).min(WIDTH)];
+                for (x, bits) in run_span(&edges, start, span, simd) {
+                    span[x].fetch_min(pack_depth_color(f32::from_bits(bits[4]), 1), Ordering::Relaxed);
+                }
+            }
+            edges.next_row();
+        }
+        depths.iter().map(|d| d.load(Ordering::Relaxed)).collect()
+    }
+
+    #[test]
+    fn simd_and_scalar_write_the_same_depths() {
+        let _switch = SIMD_SWITCH.lock().unwrap_or_else(|e| e.into_inner());
+        for fixed in triangles() {
+            let scalar = raster_depths(fixed, false);
+            let simd = raster_depths(fixed, true);
+            assert_eq!(scalar, simd, "triangle {fixed:?}");
+            // the triangle did get drawn somewhere, or there was nothing to compare
+            let untouched: Vec<u64> = depth_buffer().iter().map(|d| d.load(Ordering::Relaxed)).collect();
+            assert_ne!(scalar, untouched, "triangle {fixed:?}");
+        }
+        set_simd_enabled(true);
+    }
+
+    #[test]
+    fn pixel_centers_on_edges_follow_the_fill_rule() {
+        let _switch = SIMD_SWITCH.lock().unwrap_or_else(|e| e.into_inner());
+        let depths: Vec<AtomicU64> = (0..SPAN).map(|_| AtomicU64::new(CLEAR_DEPTH)).collect();
+        // left edge at x = 2.5 and top edge at y = 1.5 are top-left, the diagonal is not
+        let mut edges = setup(pixels([(2.5, 1.5), (2.5, 17.5), (18.5, 1.5)]));
+        edges.next_row();
+
+        for simd in [false, true] {
+            let xs: Vec<usize> = run_span(&edges, 0, &depths, simd).iter().map(|&(x, _)| x).collect();
+            // row 1: the top edge, x = 2 on the left edge up to x = 17 where the diagonal crosses
+            assert_eq!(xs, (2..18).collect::<Vec<_>>(), "simd {simd}");
+        }
+        set_simd_enabled(true);
+    }
+}
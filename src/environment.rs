@@ -0,0 +1,330 @@
+use crate::error::Result;
+use crate::framebuffer::{CLEAR_DEPTH, Framebuffer};
+use crate::pbr::{distribution_ggx, hammersley, importance_sample_ggx, integrate_brdf};
+use crate::texture::*;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+const IRRADIANCE_WIDTH: usize = 32;
+// source level the irradiance is integrated from, anything sharper only costs time
+const IRRADIANCE_SOURCE_WIDTH: usize = 64;
+const SPECULAR_WIDTH: usize = 128;
+const SPECULAR_LEVELS: usize = 6;
+const SPECULAR_SAMPLES: u32 = 64;
+const BRDF_LUT_SIZE: usize = 32;
+const BRDF_SAMPLES: u32 = 128;
+
+/// Direction to equirectangular uv: -z is the middle of the image, +y its top row.
+///
+/// ```
+/// use glam::{vec2, vec3};
+/// use ruster::environment::{direction_to_uv, uv_to_direction};
+///
+/// assert!(direction_to_uv(vec3(0.0, 0.0, -1.0)).abs_diff_eq(vec2(0.5, 0.5), 1e-6));
+/// let direction = vec3(0.3, 0.5, 0.8).normalize();
+/// assert!(uv_to_direction(direction_to_uv(direction)).abs_diff_eq(direction, 1e-5));
+/// ```
+pub fn direction_to_uv(direction: Vec3) -> Vec2 {
+    let direction = direction.normalize_or_zero();
+    Vec2::new(
+        0.5 + direction.x.atan2(-direction.z) / (2.0 * PI),
+        direction.y.clamp(-1.0, 1.0).acos() / PI,
+    )
+}
+
+pub fn uv_to_direction(uv: Vec2) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+// Linear HDR panorama, wraps around horizontally and clamps at the poles.
+#[derive(Debug, Clone)]
+pub struct EquirectMap {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Vec3>,
+}
+
+impl EquirectMap {
+    // HDR textures are already linear, 8 bit ones are assumed sRGB encoded.
+    pub fn from_texture(texture: &Texture) -> Self {
+        let texels = if texture.is_hdr() {
+            texture.hdr.iter().map(|texel| texel.truncate()).collect()
+        } else {
+            texture.data.iter().map(|argb| srgb_to_linear(vec4_from_argb(*argb).truncate())).collect()
+        };
+        Self {
+            width: texture.width,
+            height: texture.height,
+            texels,
+        }
+    }
+
+    // Evaluates `f` for the direction through every texel center, on all cores.
+    pub fn from_fn(width: usize, height: usize, f: impl Fn(Vec3) -> Vec3 + Sync) -> Self {
+        let texels = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let uv = Vec2::new((i % width) as f32 + 0.5, (i / width) as f32 + 0.5) / Vec2::new(width as f32, height as f32);
+                f(uv_to_direction(uv))
+            })
+            .collect();
+        Self { width, height, texels }
+    }
+
+    // Half the size in both directions, 2x2 box filtered.
+    pub fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (2 * x, 2 * y);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let at = |x: usize, y: usize| self.texels[y * self.width + x];
+                texels.push((at(x0, y0) + at(x1, y0) + at(x0, y1) + at(x1, y1)) * 0.25);
+            }
+        }
+        Self { width, height, texels }
+    }
+
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let uv = direction_to_uv(direction);
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (uv.y * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let at = |x: i32, y: i32| {
+            let x = x.rem_euclid(self.width as i32) as usize;
+            let y = (y as usize).min(self.height - 1);
+            self.texels[y * self.width + x]
+        };
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = at(x0, y0).lerp(at(x0 + 1, y0), fx);
+        let bottom = at(x0, y0 + 1).lerp(at(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+
+    // Solid angle a texel in `row` covers, rows near the poles are squeezed.
+    fn texel_solid_angle(&self, row: usize) -> f32 {
+        let theta = (row as f32 + 0.5) / self.height as f32 * PI;
+        (2.0 * PI / self.width as f32) * (PI / self.height as f32) * theta.sin()
+    }
+}
+
+// Image based lighting from an equirectangular panorama, precomputed for the split sum
+// approximation: cosine convolved irradiance for diffuse, GGX prefiltered radiance per
+// roughness for specular and the BRDF scale/bias lookup table.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    // source panorama and its box filtered mips, level 0 is drawn as the background
+    pub radiance: Vec<EquirectMap>,
+    // already divided by pi, so albedo * irradiance is the diffuse light
+    pub irradiance: EquirectMap,
+    // roughness 0 to 1 in even steps
+    pub specular: Vec<EquirectMap>,
+    // BRDF_LUT_SIZE^2, n dot v along x and roughness along y
+    pub brdf_lut: Vec<Vec2>,
+}
+
+impl Environment {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::from_texture(&Texture::load(path)?))
+    }
+
+    pub fn from_texture(texture: &Texture) -> Self {
+        let mut radiance = vec![EquirectMap::from_texture(texture)];
+        while radiance.last().is_some_and(|level| level.width > 1) {
+            let next = radiance.last().unwrap().downsample();
+            radiance.push(next);
+        }
+
+        let irradiance = convolve_irradiance(&radiance);
+        let specular = (0..SPECULAR_LEVELS)
+            .map(|level| prefilter_specular(&radiance, level as f32 / (SPECULAR_LEVELS - 1) as f32))
+            .collect();
+        let brdf_lut = (0..BRDF_LUT_SIZE * BRDF_LUT_SIZE)
+            .into_par_iter()
+            .map(|i| {
+                let n_dot_v = ((i % BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+                let roughness = ((i / BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+                integrate_brdf(n_dot_v, roughness, BRDF_SAMPLES)
+            })
+            .collect();
+
+        Self {
+            radiance,
+            irradiance,
+            specular,
+            brdf_lut,
+        }
+    }
+
+    pub fn background(&self, direction: Vec3) -> Vec3 {
+        self.radiance[0].sample(direction)
+    }
+
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        self.irradiance.sample(normal)
+    }
+
+    // Prefiltered radiance around the reflection direction, blended between roughness levels.
+    pub fn specular(&self, reflection: Vec3, roughness: f32) -> Vec3 {
+        let level = roughness.clamp(0.0, 1.0) * (SPECULAR_LEVELS - 1) as f32;
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(SPECULAR_LEVELS - 1);
+        self.specular[lower].sample(reflection).lerp(self.specular[upper].sample(reflection), level - lower as f32)
+    }
+
+    // (scale, bias) for f0, nearest entry of the lookup table.
+    pub fn brdf(&self, n_dot_v: f32, roughness: f32) -> Vec2 {
+        let index = |t: f32| ((t.clamp(0.0, 1.0) * BRDF_LUT_SIZE as f32) as usize).min(BRDF_LUT_SIZE - 1);
+        self.brdf_lut[index(roughness) * BRDF_LUT_SIZE + index(n_dot_v)]
+    }
+
+    // Fills every pixel no triangle covered with the panorama seen through that pixel.
+    // Run after the scene is resolved, it reads the depth buffer to find the empty pixels.
+    pub fn draw_background(&self, framebuffer: &Framebuffer, view_projection: &Mat4) {
+        let inverse = view_projection.inverse();
+        let size = framebuffer.size();
+        framebuffer
+            .buffer
+            .par_iter()
+            .zip(framebuffer.z_buffer.par_iter())
+            .enumerate()
+            .for_each(|(i, (pixel, z))| {
+                if z.load(Ordering::Relaxed) != CLEAR_DEPTH {
+                    return;
+                }
+                // pixel center back to ndc, y flipped like clip_to_screen
+                let x = ((i % framebuffer.width) as f32 + 0.5) / size.x * 2.0 - 1.0;
+                let y = 1.0 - ((i / framebuffer.width) as f32 + 0.5) / size.y * 2.0;
+                let near = inverse * Vec4::new(x, y, 0.0, 1.0);
+                let far = inverse * Vec4::new(x, y, 1.0, 1.0);
+                let direction = far.truncate() / far.w - near.truncate() / near.w;
+
+                let color = linear_to_srgb(self.background(direction));
+                pixel.store(argb_from_vec4(color.extend(1.0)), Ordering::Relaxed);
+            });
+    }
+}
+
+// Cosine weighted integral of the incoming light for every normal, brute force over a small mip.
+fn convolve_irradiance(radiance: &[EquirectMap]) -> EquirectMap {
+    let source = radiance
+        .iter()
+        .find(|level| level.width <= IRRADIANCE_SOURCE_WIDTH)
+        .unwrap_or(&radiance[radiance.len() - 1]);
+
+    // directions and solid angle weighted radiance of every source texel, shared by all normals
+    let texels: Vec<(Vec3, Vec3)> = (0..source.width * source.height)
+        .map(|i| {
+            let (x, y) = (i % source.width, i / source.width);
+            let uv = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) / Vec2::new(source.width as f32, source.height as f32);
+            (uv_to_direction(uv), source.texels[i] * source.texel_solid_angle(y))
+        })
+        .collect();
+
+    EquirectMap::from_fn(IRRADIANCE_WIDTH, IRRADIANCE_WIDTH / 2, |normal| {
+        let mut sum = Vec3::ZERO;
+        for (direction, light) in texels.iter() {
+            sum += *light * normal.dot(*direction).max(0.0);
+        }
+        sum / PI
+    })
+}
+
+// GGX importance sampled radiance with normal = view = reflection. Each sample reads a mip
+// matching the solid angle it stands for, which keeps small bright spots from turning into noise.
+fn prefilter_specular(radiance: &[EquirectMap], roughness: f32) -> EquirectMap {
+    let width = (SPECULAR_WIDTH >> (roughness * (SPECULAR_LEVELS - 1) as f32) as usize).max(8);
+    let source = &radiance[0];
+    let texel_solid_angle = 4.0 * PI / (source.width * source.height) as f32;
+
+    EquirectMap::from_fn(width, width / 2, |normal| {
+        if roughness == 0.0 {
+            return source.sample(normal);
+        }
+        let mut sum = Vec3::ZERO;
+        let mut weight = 0.0;
+        for i in 0..SPECULAR_SAMPLES {
+            let half = importance_sample_ggx(hammersley(i, SPECULAR_SAMPLES), normal, roughness);
+            let light = half * 2.0 * normal.dot(half) - normal;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            // with n = v the sample pdf reduces to D / 4
+            let pdf = distribution_ggx(normal.dot(half).max(0.0), roughness) / 4.0;
+            let sample_solid_angle = 1.0 / (SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+            let lod = (0.5 * (sample_solid_angle / texel_solid_angle).log2()).clamp(0.0, (radiance.len() - 1) as f32);
+            let level = lod.floor() as usize;
+            let upper = (level + 1).min(radiance.len() - 1);
+            let light_sample = radiance[level].sample(light).lerp(radiance[upper].sample(light), lod - level as f32);
+
+            sum += light_sample * n_dot_l;
+            weight += n_dot_l;
+        }
+        sum / weight.max(1e-4)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::pack_depth_color;
+
+    // Panorama lit by `sky` along each texel's direction.
+    fn environment(sky: impl Fn(Vec3) -> Vec3 + Sync) -> Environment {
+        let map = EquirectMap::from_fn(32, 16, sky);
+        let hdr = map.texels.iter().map(|texel| texel.extend(1.0)).collect();
+        Environment::from_texture(&Texture::from_hdr(map.width, map.height, hdr))
+    }
+
+    #[test]
+    fn uniform_environments_light_every_direction_the_same() {
+        let light = Vec3::new(0.5, 1.0, 2.0);
+        let environment = environment(|_| light);
+        for direction in [Vec3::X, -Vec3::Y, Vec3::new(0.3, 0.5, -0.8).normalize()] {
+            assert!(environment.background(direction).abs_diff_eq(light, 1e-5));
+            // the cosine integral over pi gives back the radiance
+            assert!(environment.irradiance(direction).abs_diff_eq(light, 0.02), "{}", environment.irradiance(direction));
+            for roughness in [0.0, 0.5, 1.0] {
+                assert!(environment.specular(direction, roughness).abs_diff_eq(light, 0.02));
+            }
+        }
+    }
+
+    #[test]
+    fn irradiance_follows_the_normal() {
+        // white sky over a black floor
+        let environment = environment(|direction| if direction.y > 0.0 { Vec3::ONE } else { Vec3::ZERO });
+        let irradiance = |normal: Vec3| environment.irradiance(normal).x;
+        assert!((irradiance(Vec3::Y) - 1.0).abs() < 0.03, "{}", irradiance(Vec3::Y));
+        assert!(irradiance(-Vec3::Y) < 0.03);
+        // walls see half the sky
+        assert!((irradiance(Vec3::X) - 0.5).abs() < 0.03, "{}", irradiance(Vec3::X));
+    }
+
+    #[test]
+    fn the_background_only_fills_uncovered_pixels() {
+        let environment = environment(|_| Vec3::splat(0.5));
+        let framebuffer = Framebuffer::new(8, 4);
+        framebuffer.clear(0xFF000000);
+        framebuffer.z_buffer[5].store(pack_depth_color(1.0, 0xFFFF0000), Ordering::Relaxed);
+        framebuffer.resolve();
+
+        let view_projection = Mat4::perspective_rh(1.0, 2.0, 0.1, 10.0);
+        environment.draw_background(&framebuffer, &view_projection);
+        // the panorama is linear, the framebuffer sRGB
+        let sky = argb_from_vec4(linear_to_srgb(Vec3::splat(0.5)).extend(1.0));
+        for (i, pixel) in framebuffer.buffer.iter().enumerate() {
+            let expected = if i == 5 { 0xFFFF0000 } else { sky };
+            assert_eq!(pixel.load(Ordering::Relaxed), expected, "pixel {}", i);
+        }
+    }
+}
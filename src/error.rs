@@ -0,0 +1,103 @@
+use std::fmt;
+
+// Everything that can go wrong while loading assets. Loaders return these
+// instead of panicking so a bad file can be reported and skipped.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    // the file was read but could not be decoded (broken image, invalid glTF, ...)
+    Decode(String),
+    UnsupportedPixelFormat(String),
+    MissingAttribute(&'static str),
+    IndexOutOfRange { index: u32, vertex_count: usize },
+    // a valid glTF with nothing to draw
+    NoMeshes,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Decode(message) => write!(f, "decode error: {}", message),
+            Error::UnsupportedPixelFormat(format) => write!(f, "unsupported pixel format: {}", format),
+            Error::MissingAttribute(attribute) => write!(f, "missing vertex attribute {}", attribute),
+            Error::IndexOutOfRange { index, vertex_count } => {
+                write!(f, "index {} out of range for {} vertices", index, vertex_count)
+            }
+            Error::NoMeshes => write!(f, "no meshes to draw"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(e: gltf::Error) -> Self {
+        match e {
+            gltf::Error::Io(e) => Error::Io(e),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+    use crate::scene::Scene;
+    use crate::texture::Texture;
+    use crate::utilities::load_gltf;
+    use std::path::Path;
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let missing = Path::new("does/not/exist");
+        let not_found = |result: Result<()>| matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound);
+
+        assert!(not_found(Scene::load_gltf(&missing.join("scene.gltf")).map(|_| ())));
+        assert!(not_found(load_gltf(&missing.join("scene.gltf")).map(|_| ())));
+        assert!(not_found(Texture::load(&missing.join("albedo.png")).map(|_| ())));
+    }
+
+    #[test]
+    fn gltf_without_meshes_is_an_error() {
+        let dir = TempDir::new("error-empty");
+        let path = dir.write("empty.gltf", r#"{ "asset": { "version": "2.0" }, "nodes": [{}], "scenes": [{ "nodes": [0] }], "scene": 0 }"#);
+
+        assert!(matches!(Scene::load_gltf(&path), Err(Error::NoMeshes)));
+        assert!(matches!(load_gltf(&path), Err(Error::NoMeshes)));
+    }
+
+    #[test]
+    fn undecodable_images_are_decode_errors() {
+        let dir = TempDir::new("error-image");
+        let image = dir.write("broken.png", "not an image at all");
+        assert!(matches!(Texture::load(&image), Err(Error::Decode(_))));
+
+        // the same image referenced by a glTF fails the whole import
+        let path = dir.write("textured.gltf", r#"{ "asset": { "version": "2.0" }, "images": [{ "uri": "broken.png" }] }"#);
+        assert!(matches!(Scene::load_gltf(&path), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn invalid_gltf_is_a_decode_error() {
+        let dir = TempDir::new("error-json");
+        let path = dir.write("broken.gltf", "{ this is not json");
+        assert!(matches!(Scene::load_gltf(&path), Err(Error::Decode(_))));
+    }
+}
@@ -0,0 +1,75 @@
+// Files the tests load, written to a temp directory that is removed again when the test ends.
+
+use std::path::PathBuf;
+
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    // `name` keeps tests running in parallel out of each other's files.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("ruster-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn write(&self, file: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(file);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// A glTF with one triangle facing +z, at (0, 0, 0), (1, 0, 0) and (0, 1, 0).
+// `nodes` is the json array placing it, node 0 is the scene's root.
+pub fn triangle_gltf(dir: &TempDir, nodes: &str) -> PathBuf {
+    write_triangle_gltf(dir, nodes, "", "")
+}
+
+// The same triangle drawn with material 0. `assets` holds the top level json
+// members it needs, e.g. `"materials": [...], "textures": [...], "images": [...]`.
+pub fn material_triangle_gltf(dir: &TempDir, nodes: &str, assets: &str) -> PathBuf {
+    write_triangle_gltf(dir, nodes, r#", "material": 0"#, &format!("{},", assets))
+}
+
+fn write_triangle_gltf(dir: &TempDir, nodes: &str, primitive: &str, assets: &str) -> PathBuf {
+    // positions then normals
+    let attributes: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+    dir.write("triangle.bin", attributes);
+    let json = format!(
+        r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "uri": "triangle.bin", "byteLength": 72 }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}, {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }}],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" }}
+            ],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0, "NORMAL": 1 }}{} }}] }}],
+            {}
+            "nodes": {},
+            "scenes": [{{ "nodes": [0] }}],
+            "scene": 0
+        }}"#,
+        primitive, assets, nodes
+    );
+    dir.write("scene.gltf", json)
+}
+
+// Encodes `data` (rows top to bottom, big endian for 16 bit) as a PNG file.
+pub fn png(width: u32, height: u32, color: png::ColorType, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    encoder.write_header().unwrap().write_image_data(data).unwrap();
+    bytes
+}
@@ -1,5 +1,233 @@
-use std::sync::atomic::AtomicU32;
+use crate::geometry::TileGrid;
+use glam::Vec2;
+use rayon::prelude::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
+// Depth and color packed in one u64, depth (clip z) in the high bits. Depth is a positive float
+// so its bits sort like the float itself, and a single fetch_min does the depth test and
+// the color write together: no tile can slip a far color in between the two.
+// Equal depths resolve to the smaller color, independent of thread scheduling.
+pub fn pack_depth_color(depth: f32, color: u32) -> u64 {
+    ((depth.to_bits() as u64) << 32) | color as u64
+}
+
+pub fn unpack_depth(packed: u64) -> f32 {
+    f32::from_bits((packed >> 32) as u32)
+}
+
+pub fn unpack_color(packed: u64) -> u32 {
+    packed as u32
+}
+
+pub const CLEAR_DEPTH: u64 = (f32::INFINITY.to_bits() as u64) << 32;
+
+// Color and depth targets plus their size, so every raster path reads
+// the resolution from here instead of a global constant.
 pub struct Framebuffer {
-    pub buffer: Vec<AtomicU32 >,
+    pub width: usize,
+    pub height: usize,
+    pub buffer: Vec<AtomicU32>,
+    pub z_buffer: Vec<AtomicU64>,
+    // tiles and bins of the binned renderer, reused by every frame drawn into this target
+    pub tile_grid: Mutex<TileGrid>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            buffer: (0..width * height).map(|_| AtomicU32::new(0)).collect(),
+            z_buffer: (0..width * height).map(|_| AtomicU64::new(CLEAR_DEPTH)).collect(),
+            tile_grid: Mutex::new(TileGrid::new(width, height, TileGrid::DEFAULT_TILE_SIZE, TileGrid::DEFAULT_TILE_SIZE)),
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        glam::vec2(self.width as f32, self.height as f32)
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    // Reallocates both targets, previous contents are discarded. The tile size is kept.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            let grid = self.tile_grid.get_mut().unwrap_or_else(|e| e.into_inner());
+            let (tile_width, tile_height) = (grid.tile_width, grid.tile_height);
+            *self = Self::new(width, height);
+            self.set_tile_size(tile_width, tile_height);
+        }
+    }
+
+    // Tiles don't have to be square, wide ones suit wide framebuffers.
+    pub fn set_tile_size(&mut self, tile_width: usize, tile_height: usize) {
+        let grid = self.tile_grid.get_mut().unwrap_or_else(|e| e.into_inner());
+        *grid = TileGrid::new(self.width, self.height, tile_width, tile_height);
+    }
+
+    pub fn clear(&self, color: u32) {
+        for pixel in self.buffer.iter() {
+            pixel.store(color, Ordering::Relaxed);
+        }
+        for z in self.z_buffer.iter() {
+            z.store(CLEAR_DEPTH, Ordering::Relaxed);
+        }
+    }
+
+    // Copies the winning color of every covered pixel from the depth buffer to the color buffer.
+    pub fn resolve(&self) {
+        self.buffer.par_iter().zip(self.z_buffer.par_iter()).for_each(|(pixel, z)| {
+            let packed = z.load(Ordering::Relaxed);
+            if packed != CLEAR_DEPTH {
+                pixel.store(unpack_color(packed), Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{MeshRenderer, Vertex, render_mesh};
+    use crate::shader::{TextureShader, TransformShader};
+    use glam::{Mat4, UVec3, Vec3, Vec4, vec4};
+
+    #[test]
+    fn resize_reallocates_both_targets() {
+        let mut framebuffer = Framebuffer::new(256, 256);
+        framebuffer.buffer[0].store(7, Ordering::Relaxed);
+
+        framebuffer.resize(320, 180);
+        assert_eq!((framebuffer.width, framebuffer.height), (320, 180));
+        assert_eq!(framebuffer.size(), glam::vec2(320.0, 180.0));
+        assert_eq!(framebuffer.aspect_ratio(), 320.0 / 180.0);
+        assert_eq!(framebuffer.buffer.len(), 320 * 180);
+        assert_eq!(framebuffer.z_buffer.len(), 320 * 180);
+        assert_eq!(framebuffer.buffer[0].load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn clear_resets_color_and_depth() {
+        let framebuffer = Framebuffer::new(3, 2);
+        framebuffer.z_buffer[4].store(pack_depth_color(0.5, 9), Ordering::Relaxed);
+
+        framebuffer.clear(0xff00ff00);
+        assert!(framebuffer.buffer.iter().all(|pixel| pixel.load(Ordering::Relaxed) == 0xff00ff00));
+        assert!(framebuffer.z_buffer.iter().all(|z| z.load(Ordering::Relaxed) == CLEAR_DEPTH));
+    }
+
+    // Every task writes `depth(task)` and the color `task` into every pixel, like overlapping tiles would.
+    fn race(tasks: u32, depth: impl Fn(u32) -> f32 + Sync) -> Framebuffer {
+        let framebuffer = Framebuffer::new(16, 16);
+        framebuffer.clear(0);
+        (0..tasks).into_par_iter().for_each(|task| {
+            for z in framebuffer.z_buffer.iter() {
+                z.fetch_min(pack_depth_color(depth(task), task), Ordering::Relaxed);
+            }
+        });
+        framebuffer.resolve();
+        framebuffer
+    }
+
+    fn colors(framebuffer: &Framebuffer) -> Vec<u32> {
+        framebuffer.buffer.iter().map(|pixel| pixel.load(Ordering::Relaxed)).collect()
+    }
+
+    #[test]
+    fn nearest_depth_wins_the_race() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        // nearest is task 37, neither the first nor the last to run
+        let depth = |task: u32| if task == 37 { 0.01 } else { 0.5 + (task % 7) as f32 * 0.1 };
+        for _ in 0..20 {
+            let framebuffer = pool.install(|| race(64, depth));
+            assert!(colors(&framebuffer).iter().all(|&color| color == 37));
+            assert!(framebuffer.z_buffer.iter().all(|z| unpack_depth(z.load(Ordering::Relaxed)) == 0.01));
+        }
+    }
+
+    #[test]
+    fn equal_depths_resolve_the_same_every_time() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        // every task at the same depth, the smallest color wins whatever order they ran in
+        for _ in 0..20 {
+            let framebuffer = pool.install(|| race(64, |_| 0.25));
+            assert!(colors(&framebuffer).iter().all(|&color| color == 0));
+        }
+        let framebuffer = pool.install(|| race(64, |task| if task < 10 { 0.9 } else { 0.25 }));
+        assert!(colors(&framebuffer).iter().all(|&color| color == 10));
+    }
+
+    #[test]
+    fn resolve_leaves_uncovered_pixels_alone() {
+        let framebuffer = Framebuffer::new(4, 4);
+        framebuffer.clear(7);
+        framebuffer.z_buffer[5].fetch_min(pack_depth_color(0.5, 9), Ordering::Relaxed);
+        framebuffer.resolve();
+        let expected: Vec<u32> = (0..16).map(|i| if i == 5 { 9 } else { 7 }).collect();
+        assert_eq!(colors(&framebuffer), expected);
+    }
+
+    // How many tiles of the framebuffer's grid hold each pixel.
+    fn tiles_per_pixel(framebuffer: &Framebuffer) -> Vec<u32> {
+        let grid = framebuffer.tile_grid.lock().unwrap();
+        let mut counts = vec![0; framebuffer.width * framebuffer.height];
+        for tile in grid.tiles.iter() {
+            for y in tile.min_y..tile.max_y {
+                for x in tile.min_x..tile.max_x {
+                    counts[y as usize * framebuffer.width + x as usize] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    // Covers the whole screen, so every pixel gets written whatever tile it is in.
+    fn fill(framebuffer: &Framebuffer) {
+        let corners = [vec4(-1.0, -1.0, 0.5, 1.0), vec4(1.0, -1.0, 0.5, 1.0), vec4(1.0, 1.0, 0.5, 1.0), vec4(-1.0, 1.0, 0.5, 1.0)];
+        let vertices = corners.map(|p| Vertex::new(p, Vec3::Z, Vec3::ONE, glam::Vec2::ZERO));
+        let quad = MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)], &vertices);
+        framebuffer.clear(0);
+        render_mesh(&quad, &TransformShader { mvp: Mat4::IDENTITY }, &TextureShader { texture: None, base_color: Vec4::ONE }, framebuffer, false);
+    }
+
+    #[test]
+    fn edge_tiles_are_clamped_to_the_framebuffer() {
+        let framebuffer = Framebuffer::new(317, 91);
+        {
+            let grid = framebuffer.tile_grid.lock().unwrap();
+            assert_eq!((grid.columns, grid.rows), (5, 2));
+            let corner = &grid.tiles[grid.id(4, 1)];
+            assert_eq!((corner.min_x, corner.min_y, corner.max_x, corner.max_y), (256, 64, 317, 91));
+            // row major ids
+            let second_row = &grid.tiles[grid.id(0, 1)];
+            assert_eq!((grid.id(0, 1), second_row.min_x, second_row.min_y), (5, 0, 64));
+        }
+        assert!(tiles_per_pixel(&framebuffer).iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn tiles_larger_than_the_framebuffer_become_one_tile() {
+        let mut framebuffer = Framebuffer::new(317, 91);
+        framebuffer.set_tile_size(500, 128);
+        {
+            let grid = framebuffer.tile_grid.lock().unwrap();
+            assert_eq!((grid.columns, grid.rows, grid.len()), (1, 1, 1));
+            let tile = &grid.tiles[0];
+            assert_eq!((tile.min_x, tile.min_y, tile.max_x, tile.max_y), (0, 0, 317, 91));
+        }
+        fill(&framebuffer);
+        assert!(framebuffer.buffer.iter().all(|pixel| pixel.load(Ordering::Relaxed) != 0));
+
+        // only one side too large, and the requested size survives a resize
+        framebuffer.set_tile_size(32, 200);
+        framebuffer.resize(640, 480);
+        let grid = framebuffer.tile_grid.lock().unwrap();
+        assert_eq!((grid.tile_width, grid.tile_height, grid.columns, grid.rows), (32, 200, 20, 3));
+        assert_eq!(grid.tiles[grid.id(19, 2)].max_y, 480);
+        drop(grid);
+        assert!(tiles_per_pixel(&framebuffer).iter().all(|&count| count == 1));
+    }
 }
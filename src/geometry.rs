@@ -1,17 +1,19 @@
-use bevy::math::ops::ceil;
-use glam::{UVec3, Vec2, Vec3, Vec4, Mat4};
-use std::ops::{Add, AddAssign, MulAssign, Sub, Mul};
+use glam::{I64Vec2, UVec3, Vec2, Vec3, Vec4};
+use std::ops::{Add, AddAssign, MulAssign, Range, Sub, Mul};
+use crate::clipping::*;
+use crate::coverage::{Covered, EdgeSetup, SPAN};
+use crate::error::{Error, Result};
+use crate::framebuffer::*;
+use crate::shader::*;
 use crate::texture::*;
 use crate::utilities::*;
-use crate::window::SCREEN_HEIGHT;
-use crate::window::SCREEN_WIDTH;
-use bevy::prelude::ops::floor;
 use rand::Rng;
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use std::thread;
 use std::cmp::Ordering;
-use std::sync::atomic::{AtomicU32};
+use std::sync::atomic::{AtomicU32, AtomicU64};
+use std::sync::PoisonError;
 use rayon::prelude::*;
 
 #[derive(Debug, Copy, Clone)]
@@ -21,6 +23,9 @@ pub struct Vertex
     pub normal: Vec3,
     pub color: Vec3,
     pub uv: Vec2,
+    // glTF tangent: xyz along +u, w the bitangent sign (bitangent = cross(normal, xyz) * w).
+    // Zero when the mesh has no uvs to build one from.
+    pub tangent: Vec4,
 }
 
 impl Vertex {
@@ -30,8 +35,13 @@ impl Vertex {
             normal,
             color,
             uv,
+            tangent: Vec4::ZERO,
         }
     }
+
+    pub fn with_tangent(self, tangent: Vec4) -> Self {
+        Self { tangent, ..self }
+    }
 }
 
 impl Add for Vertex {
@@ -42,7 +52,8 @@ impl Add for Vertex {
         let normal = self.normal + rhs.normal;
         let color = self.color + rhs.color;
         let uv = self.uv + rhs.uv;
-        Self::new(position, normal, color, uv)
+        let tangent = self.tangent + rhs.tangent;
+        Self::new(position, normal, color, uv).with_tangent(tangent)
     }
 }
 
@@ -54,7 +65,8 @@ impl Sub for Vertex {
         let normal = self.normal - rhs.normal;
         let color = self.color - rhs.color;
         let uv = self.uv - rhs.uv;
-        Self::new(position, normal, color, uv)
+        let tangent = self.tangent - rhs.tangent;
+        Self::new(position, normal, color, uv).with_tangent(tangent)
     }
 }
 
@@ -66,15 +78,18 @@ impl Mul<f32> for Vertex {
         let normal = self.normal * rhs;
         let color = self.color * rhs;
         let uv = self.uv * rhs;
-        Self::new(position, normal, color, uv)
+        let tangent = self.tangent * rhs;
+        Self::new(position, normal, color, uv).with_tangent(tangent)
     }
 }
 
 impl MulAssign<f32> for Vertex {
     fn mul_assign(&mut self, rhs: f32) {
         self.position *= rhs;
+        self.normal *= rhs;
         self.color *= rhs;
         self.uv *= rhs;
+        self.tangent *= rhs;
     }
 }
 
@@ -92,6 +107,13 @@ impl MeshRenderer {
         }
     }
 
+    // Appends another mesh, its triangles moved past the vertices already there.
+    pub fn append(&mut self, other: &MeshRenderer) {
+        let offset = self.vertices.len() as u32;
+        self.triangles.extend(other.triangles.iter().map(|tri| *tri + offset));
+        self.vertices.extend_from_slice(&other.vertices);
+    }
+
     pub fn triangles(&self) -> &Vec<UVec3> {
         &self.triangles
     }
@@ -129,6 +151,7 @@ impl MeshRenderer {
     normals: &[Vec3],
     colors: &[Vec3],
     uvs: &[Vec2],
+    tangents: &[Vec4],
 ) {
     // Calculate offset before adding new vertices
     let offset = self.vertices.len() as u32;
@@ -142,6 +165,7 @@ impl MeshRenderer {
 
     let has_uvs = !uvs.is_empty();
     let has_colors = !colors.is_empty();
+    let has_tangents = !tangents.is_empty();
 
     for i in 0..positions.len() {
         let vertex = Vertex::new(
@@ -149,56 +173,212 @@ impl MeshRenderer {
             normals[i],
             if has_colors { colors[i] } else { Vec3::ONE },
             if has_uvs { uvs[i] } else { Vec2::ZERO },
-        );
+        )
+        .with_tangent(if has_tangents { tangents[i] } else { Vec4::ZERO });
         self.vertices.push(vertex)
     }
 }
 
-    pub fn load_from_gltf(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> MeshRenderer {
-        let mut positions: Vec<Vec3> = Vec::new();
+    pub fn load_from_gltf(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Result<MeshRenderer> {
+        let mut result = MeshRenderer::new();
+        for primitive in mesh.primitives() {
+            result += MeshRenderer::load_primitive_from_gltf(&primitive, buffers)?;
+        }
+        Ok(result)
+    }
+
+    pub fn load_primitive_from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<MeshRenderer> {
         let mut tex_coords: Vec<Vec2> = Vec::new();
         let mut normals: Vec<Vec3> = Vec::new();
+        let mut tangents: Vec<Vec4> = Vec::new();
         let mut indices = vec![];
-        // TODO: handle errors
         let mut result = MeshRenderer::new();
-        for primitive in mesh.primitives() {
-            positions.clear();
-            tex_coords.clear();
-            normals.clear();
-            indices.clear();
-
-            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-            if let Some(indices_reader) = reader.read_indices() {
-                indices_reader.into_u32().for_each(|i| indices.push(i));
-            }
-            if let Some(positions_reader) = reader.read_positions() {
-                positions_reader.for_each(|p| positions.push(Vec3::new(p[0], p[1], p[2])));
-            }
-            if let Some(normals_reader) = reader.read_normals() {
-                normals_reader.for_each(|n| normals.push(Vec3::new(n[0], n[1], n[2])));
-            }
-            if let Some(tex_coord_reader) = reader.read_tex_coords(0) {
-                tex_coord_reader
-                    .into_f32()
-                    .for_each(|tc| tex_coords.push(Vec2::new(tc[0], tc[1])));
-            }
 
-            let colors: Vec<Vec3> = positions.iter().map(|_| Vec3::ONE).collect();
-            println!("Num indices: {:?}", indices.len());
-            println!("tex_coords: {:?}", tex_coords.len());
-            println!("positions: {:?}", positions.len());
+        // points and lines have nothing to rasterize
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Ok(result);
+        }
 
-            let triangles: Vec<UVec3> = indices
-                .chunks_exact(3)
-                .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
-                .collect();
-            result.add_section_from_buffers(&triangles, &positions, &normals, &colors, &tex_coords)
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+        let mut positions: Vec<Vec3> = reader
+            .read_positions()
+            .ok_or(Error::MissingAttribute("POSITION"))?
+            .map(Vec3::from_array)
+            .collect();
+        if let Some(indices_reader) = reader.read_indices() {
+            indices_reader.into_u32().for_each(|i| indices.push(i));
+        } else {
+            // non indexed primitives list their vertices in triangle order
+            indices.extend(0..positions.len() as u32);
+        }
+        if let Some(normals_reader) = reader.read_normals() {
+            normals_reader.for_each(|n| normals.push(Vec3::new(n[0], n[1], n[2])));
+        }
+        if let Some(tangents_reader) = reader.read_tangents() {
+            tangents_reader.for_each(|t| tangents.push(Vec4::from_array(t)));
+        }
+        if let Some(tex_coord_reader) = reader.read_tex_coords(0) {
+            tex_coord_reader
+                .into_f32()
+                .for_each(|tc| tex_coords.push(Vec2::new(tc[0], tc[1])));
+        }
+
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(Error::IndexOutOfRange { index, vertex_count: positions.len() });
+        }
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(Error::Decode(format!("{} normals for {} positions", normals.len(), positions.len())));
+        }
+        if !tex_coords.is_empty() && tex_coords.len() != positions.len() {
+            return Err(Error::Decode(format!("{} uvs for {} positions", tex_coords.len(), positions.len())));
         }
-        result
+        if !tangents.is_empty() && tangents.len() != positions.len() {
+            return Err(Error::Decode(format!("{} tangents for {} positions", tangents.len(), positions.len())));
+        }
+
+        let mut triangles: Vec<UVec3> = indices
+            .chunks_exact(3)
+            .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
+            .collect();
+
+        // glTF allows leaving normals out, fall back to smooth normals from the faces
+        if normals.is_empty() {
+            normals = vertex_normals(&triangles, &positions);
+        }
+        // and tangents, which only mean something with uvs to follow
+        if tangents.is_empty() && !tex_coords.is_empty() {
+            // after the normals, so both sides of a mirror line keep the same smooth normal
+            let (generated, sources) = generate_tangents(&mut triangles, &positions, &normals, &tex_coords);
+            for source in sources {
+                let source = source as usize;
+                positions.push(positions[source]);
+                normals.push(normals[source]);
+                tex_coords.push(tex_coords[source]);
+            }
+            tangents = generated;
+        }
+
+        let colors: Vec<Vec3> = positions.iter().map(|_| Vec3::ONE).collect();
+        result.add_section_from_buffers(&triangles, &positions, &normals, &colors, &tex_coords, &tangents);
+        Ok(result)
     }
 
 }
 
+// Area weighted average of the face normals around each vertex.
+pub fn vertex_normals(triangles: &[UVec3], positions: &[Vec3]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for tri in triangles {
+        let [a, b, c] = [tri.x as usize, tri.y as usize, tri.z as usize];
+        // cross product length is twice the area, no need to normalize here
+        let face = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        normals[a] += face;
+        normals[b] += face;
+        normals[c] += face;
+    }
+    normals.iter().map(|n| n.normalize_or_zero()).collect()
+}
+
+// The triangles as MikkTSpace reads them, and the tangent it generates for every corner.
+struct TangentSpaceInput<'a> {
+    triangles: &'a [UVec3],
+    positions: &'a [Vec3],
+    normals: &'a [Vec3],
+    uvs: &'a [Vec2],
+    corners: Vec<Vec4>,
+}
+
+impl TangentSpaceInput<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.triangles[face][vert] as usize
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentSpaceInput<'_> {
+    fn num_faces(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)].to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)].to_array()
+    }
+
+    // v flipped so the bitangent follows the normal map's green (up) instead of glTF's v pointing down the image
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let uv = self.uvs[self.vertex(face, vert)];
+        [uv.x, -uv.y]
+    }
+
+    // degenerate corners get no tangent, the shaders then build one from the uv derivatives
+    fn set_tangent(&mut self, tangent_space: Option<bevy_mikktspace::TangentSpace>, face: usize, vert: usize) {
+        self.corners[face * 3 + vert] = tangent_space.map_or(Vec4::W, |t| Vec4::from_array(t.tangent_encoded()));
+    }
+}
+
+/// MikkTSpace tangents, the ones glTF normal maps are baked against, with the bitangent sign in w.
+/// MikkTSpace picks a tangent per triangle corner. Where the corners sharing a vertex disagree, e.g.
+/// on the seam between mirrored uv islands, the vertex gets a copy for every other tangent and those
+/// corners are re-indexed to it. Returns a tangent per vertex, copies included, and the vertex each
+/// copy was made from; the caller appends their other attributes in that order.
+///
+/// ```
+/// use glam::{UVec3, vec2, vec3, vec4};
+/// use ruster::geometry::generate_tangents;
+///
+/// // quad facing +z, glTF uvs start at the top left
+/// let positions = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)];
+/// let uvs = [vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(1.0, 0.0), vec2(0.0, 0.0)];
+/// let mut triangles = [UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)];
+/// let (tangents, copies) = generate_tangents(&mut triangles, &positions, &[vec3(0.0, 0.0, 1.0); 4], &uvs);
+/// assert!(copies.is_empty());
+/// assert!(tangents.iter().all(|t| t.abs_diff_eq(vec4(1.0, 0.0, 0.0, 1.0), 1e-5)));
+/// ```
+pub fn generate_tangents(triangles: &mut [UVec3], positions: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> (Vec<Vec4>, Vec<u32>) {
+    let mut input = TangentSpaceInput {
+        triangles,
+        positions,
+        normals,
+        uvs,
+        corners: vec![Vec4::W; triangles.len() * 3],
+    };
+    // its error type has no variants
+    let _ = bevy_mikktspace::generate_tangents(&mut input);
+    let corners = input.corners;
+
+    // the first corner reaching a vertex sets its tangent
+    let mut tangents: Vec<Option<Vec4>> = vec![None; positions.len()];
+    let mut copies: Vec<Vec<u32>> = vec![Vec::new(); positions.len()];
+    let mut sources = Vec::new();
+    let corner_indices = triangles.iter_mut().flat_map(|tri| [&mut tri.x, &mut tri.y, &mut tri.z]);
+    for (&tangent, index) in corners.iter().zip(corner_indices) {
+        let vertex = *index as usize;
+        match tangents[vertex] {
+            None => tangents[vertex] = Some(tangent),
+            Some(current) if current.abs_diff_eq(tangent, 1e-6) => {}
+            Some(_) => {
+                // corners disagreeing with it share a copy made for their tangent
+                let copy = copies[vertex].iter().copied().find(|&copy| tangents[copy as usize].is_some_and(|other| other.abs_diff_eq(tangent, 1e-6)));
+                *index = copy.unwrap_or_else(|| {
+                    let copy = tangents.len() as u32;
+                    tangents.push(Some(tangent));
+                    copies[vertex].push(copy);
+                    sources.push(vertex as u32);
+                    copy
+                });
+            }
+        }
+    }
+    (tangents.into_iter().map(|tangent| tangent.unwrap_or(Vec4::W)).collect(), sources)
+}
+
 // for more on struct initialization check Default trait
 impl Default for MeshRenderer {
     fn default() -> Self {
@@ -209,68 +389,162 @@ impl Default for MeshRenderer {
 impl Add for MeshRenderer {
     type Output = Self;
 
-    fn add(self, rhs: Self) -> Self {
-        let mut result = Self::from_vertices(self.triangles(), self.vertices());
-        result.add_section_from_vertices(rhs.triangles(), rhs.vertices());
-        result
+    fn add(mut self, rhs: Self) -> Self {
+        self.append(&rhs);
+        self
     }
 }
 
 impl AddAssign for MeshRenderer {
     fn add_assign(&mut self, rhs: Self) {
-        self.add_section_from_vertices(rhs.triangles(), rhs.vertices());
+        self.append(&rhs);
     }
 }
 
 
-pub fn raster_triangle(
+// Screen position of a clip space point, after perspective division.
+// Rows go top to bottom like the images we hand the framebuffer to, so ndc y = 1 is row 0.
+// With y down, counter clockwise (front facing) triangles get a positive edge_function area.
+pub fn clip_to_screen(clip: Vec4, viewport_size: Vec2) -> Vec2 {
+    let ndc = clip / clip.w;
+    glam::vec2(
+        map_to_range(ndc.x, -1.0, 1.0, 0.0, viewport_size.x),
+        map_to_range(ndc.y, -1.0, 1.0, viewport_size.y, 0.0),
+    )
+}
+
+// A vertex after the vertex stage: the shader output, kept for clipping, and everything
+// rasterizing needs from it after the perspective division.
+#[derive(Clone, Copy)]
+pub struct TransformedVertex<V> {
+    pub output: VertexOutput<V>,
+    // frustum planes it is outside of, see clipping::outcode
+    pub outcode: u8,
+    // snapped screen position, meaningless behind the camera (outcode != 0)
+    pub screen: I64Vec2,
+    pub rec_w: f32,
+    pub z_over_w: f32,
+    pub varyings_over_w: V,
+}
+
+impl<V: Varyings> TransformedVertex<V> {
+    pub fn new(output: VertexOutput<V>, viewport_size: Vec2) -> Self {
+        let rec_w = 1.0 / output.position.w;
+        Self {
+            output,
+            outcode: outcode(output.position),
+            screen: to_fixed(clip_to_screen(output.position, viewport_size)),
+            rec_w,
+            z_over_w: output.position.z * rec_w,
+            varyings_over_w: output.varyings * rec_w,
+        }
+    }
+}
+
+// Vertex stage: shades every vertex of the mesh once, in parallel. Triangles index into
+// the result, so shared vertices and triangles binned into several tiles aren't shaded again.
+pub fn transform_vertices<VS: VertexShader>(mesh: &MeshRenderer, vertex_shader: &VS, viewport_size: Vec2) -> Vec<TransformedVertex<VS::Varyings>> {
+    mesh.vertices()
+        .par_iter()
+        .map(|vertex| TransformedVertex::new(vertex_shader.shade(vertex), viewport_size))
+        .collect()
+}
+
+// A draw call after the vertex stage, what binning and the tiles work from.
+pub struct TransformedDraw<'a, V, FS> {
+    pub mesh: &'a MeshRenderer,
+    pub fragment_shader: &'a FS,
+    pub vertices: Vec<TransformedVertex<V>>,
+}
+
+// The three vertex stage outputs of a triangle.
+pub fn transformed_triangle<V: Copy>(transformed: &[TransformedVertex<V>], triangle: UVec3) -> [TransformedVertex<V>; 3] {
+    [
+        transformed[triangle.x as usize],
+        transformed[triangle.y as usize],
+        transformed[triangle.z as usize],
+    ]
+}
+
+// Runs the vertex shader on the three corners of a triangle.
+pub fn shade_triangle<VS: VertexShader>(vertices: &[&Vertex; 3], vertex_shader: &VS) -> [VertexOutput<VS::Varyings>; 3] {
+    vertices.map(|v| vertex_shader.shade(v))
+}
+
+pub fn raster_triangle<VS, FS>(
     vertices: &[&Vertex; 3],
-    mvp: &Mat4,
-    texture: Option<&Texture>,
-    buffer: &[AtomicU32],
-    z_buffer: &[AtomicU32],
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+    z_buffer: &[AtomicU64],
     viewport_size: Vec2,
+    tile: &Tile,
+) where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
+{
+    // Clip before dividing by w, vertices behind the camera would flip across the screen.
+    let polygon = clip_triangle(&shade_triangle(vertices, vertex_shader));
+    for triangle in triangulate(&polygon) {
+        raster_clipped_triangle(&triangle, fragment_shader, z_buffer, viewport_size, tile);
+    }
+}
+
+// Rasterizes a triangle from the vertex stage, clipping it only when it crosses the frustum.
+pub fn raster_transformed_triangle<V: Varyings, FS: FragmentShader<V>>(
+    vertices: &[TransformedVertex<V>; 3],
+    fragment_shader: &FS,
+    z_buffer: &[AtomicU64],
+    viewport_size: Vec2,
+    tile: &Tile,
 ) {
-    let clip0 = *mvp * vertices[0].position;
-    let clip1 = *mvp * vertices[1].position;
-    let clip2 = *mvp * vertices[2].position;
-
-    let rec0 = 1.0 / clip0.w;
-    let rec1 = 1.0 / clip1.w;
-    let rec2 = 1.0 / clip2.w;
-
-    // This would be the output of the vertex shader (clip space)
-    // then we perform perspective division to transform in ndc
-    // now x,y,z componend of ndc are between -1 and 1
-    let ndc0 = clip0 * rec0;
-    let ndc1 = clip1 * rec1;
-    let ndc2 = clip2 * rec2;
-
-    // perspective division on all attributes
-    let v0 = *vertices[0] * rec0;
-    let v1 = *vertices[1] * rec1;
-    let v2 = *vertices[2] * rec2;
-
-    // screeen coordinates remapped to window
-    let sc0 = glam::vec2(
-    map_to_range(ndc0.x, -1.0, 1.0, 0.0, viewport_size.x),
-    map_to_range(ndc0.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-    let sc1 = glam::vec2(
-        map_to_range(ndc1.x, -1.0, 1.0, 0.0, viewport_size.x),
-        map_to_range(ndc1.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-    let sc2 = glam::vec2(
-        map_to_range(ndc2.x, -1.0, 1.0, 0.0, viewport_size.x),
-        map_to_range(ndc2.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-
-    let area = edge_function(sc0, sc1, sc2);
-
-    // backface culling
-    if area <= 0.0 {
+    if vertices.iter().all(|v| v.outcode == 0) {
+        raster_screen_triangle(vertices, fragment_shader, z_buffer, viewport_size, tile);
+        return;
+    }
+    if vertices.iter().fold(u8::MAX, |code, v| code & v.outcode) != 0 {
         return;
     }
+    let polygon = clip_triangle(&vertices.map(|v| v.output));
+    for triangle in triangulate(&polygon) {
+        raster_clipped_triangle(&triangle, fragment_shader, z_buffer, viewport_size, tile);
+    }
+}
+
+// Rasterizes a triangle that is already in clip space and inside the frustum.
+pub fn raster_clipped_triangle<V: Varyings, FS: FragmentShader<V>>(
+    vertices: &[VertexOutput<V>; 3],
+    fragment_shader: &FS,
+    z_buffer: &[AtomicU64],
+    viewport_size: Vec2,
+    tile: &Tile,
+) {
+    let vertices = vertices.map(|v| TransformedVertex::new(v, viewport_size));
+    raster_screen_triangle(&vertices, fragment_shader, z_buffer, viewport_size, tile);
+}
+
+// Rasterizes a triangle inside the frustum from its post-transform vertices.
+// Colors land in the packed z_buffer, Framebuffer::resolve copies them out.
+// Only pixels inside `tile` are touched, so tiles never shade each other's pixels.
+pub fn raster_screen_triangle<V: Varyings, FS: FragmentShader<V>>(
+    vertices: &[TransformedVertex<V>; 3],
+    fragment_shader: &FS,
+    z_buffer: &[AtomicU64],
+    viewport_size: Vec2,
+    tile: &Tile,
+) {
+    // clip space to ndc (x,y,z between -1 and 1) and remapped to window,
+    // snapped to the sub-pixel grid so coverage is decided with exact integer math
+    let fixed = vertices.map(|v| v.screen);
+    let area_fixed = edge_function_fixed(fixed[0], fixed[1], fixed[2]);
+
+    // backface culling, slivers that snap to nothing cover no pixel either
+    if area_fixed <= 0 {
+        return;
+    }
+
+    // the float positions the interpolator works with are the snapped ones too
+    let [sc0, sc1, sc2] = fixed.map(from_fixed);
+    let area = area_fixed as f32 / (SUBPIXEL_SCALE * SUBPIXEL_SCALE);
 
     // AABB to avoid iterating through the whole buffer
     let min_x = sc0.x.min(sc1.x).min(sc2.x).floor() as i32;
@@ -278,87 +552,80 @@ pub fn raster_triangle(
     let min_y = sc0.y.min(sc1.y).min(sc2.y).floor() as i32;
     let max_y = sc0.y.max(sc1.y).max(sc2.y).ceil() as i32;
 
-    // Clamp to screen bounds
-    let min_x = min_x.max(0) as usize;
-    let max_x = max_x.min(viewport_size.x as i32) as usize;
-    let min_y = min_y.max(0) as usize;
-    let max_y = max_y.min(viewport_size.y as i32) as usize;
+    // Clamp to the tile and screen bounds
+    let min_x = min_x.max(tile.min_x).max(0);
+    let max_x = max_x.min(tile.max_x).min(viewport_size.x as i32);
+    let min_y = min_y.max(tile.min_y).max(0);
+    let max_y = max_y.min(tile.max_y).min(viewport_size.y as i32);
+    if min_x >= max_x || min_y >= max_y {
+        return;
+    }
+    let (min_x, max_x, min_y, max_y) = (min_x as usize, max_x as usize, min_y as usize, max_y as usize);
+
+    // perspective division on all varyings was done by the vertex stage, undone per pixel by the interpolator
+    let rec_w = vertices.map(|v| v.rec_w);
+    let z_over_w = vertices.map(|v| v.z_over_w);
+    let interpolator = Interpolator {
+        screen: [sc0, sc1, sc2],
+        area,
+        varyings: vertices.map(|v| v.varyings_over_w),
+        rec_w,
+    };
+
+    // edges stepped from the first pixel, pixels exactly on an edge that isn't top-left
+    // belong to the neighbouring triangle
+    let mut edges = EdgeSetup::new(fixed, rec_w, z_over_w, min_x, min_y);
+    let mut covered = [Covered::default(); SPAN];
 
     for y in min_y..max_y {
-        for x in min_x..max_x {
-            let coords = glam::vec2(x as f32, y as f32) + 0.5;
-            let i = coords_to_index(x, y, viewport_size.x as usize);
-
-            if let Some(bary) = barycentric_coordinates(coords, sc0, sc1, sc2, area) {
-                let correction = bary.x * rec0 + bary.y * rec1 + bary.z * rec2;
-                let depth = correction;
-                let correction = 1.0 / correction;
-
-                if depth < f32::from_bits(z_buffer[i].load(std::sync::atomic::Ordering::Relaxed)) {
-                    z_buffer[i].store(depth.to_bits(), std::sync::atomic::Ordering::Relaxed);
-                    let color = bary.x * v0.color + bary.y * v1.color + bary.z * v2.color;
-                    let color = color * correction;
-                    let mut color = to_argb(
-                        255,
-                        (color.x * 255.0) as u8,
-                        (color.y * 255.0) as u8,
-                        (color.z * 255.0) as u8,
-                    );
-                    
-                    if let Some(tex) = texture {
-                        let tex_coords = bary.x * v0.uv + bary.y * v1.uv + bary.z * v2.uv;
-                        let tex_coords = tex_coords * correction;
-                        color = tex.argb_at_uv(tex_coords.x, tex_coords.y);
-                    }
-                    
-                    buffer[i].store(color, std::sync::atomic::Ordering::Relaxed)
+        let row = coords_to_index(0, y, viewport_size.x as usize);
+
+        for span_start in (min_x..max_x).step_by(SPAN) {
+            let span_end = (span_start + SPAN).min(max_x);
+            let count = edges.span(span_start - min_x, &z_buffer[row + span_start..row + span_end], &mut covered);
+
+            for pixel in covered[..count].iter() {
+                let x = span_start + pixel.x;
+                let fragment = Fragment {
+                    varyings: interpolator.at_barycentric(pixel.bary),
+                    position: glam::vec2(x as f32, y as f32) + 0.5,
+                    depth: pixel.depth,
+                    interpolator: &interpolator,
+                };
+
+                // None discards the fragment, depth stays untouched
+                if let Some(rgba) = fragment_shader.shade(&fragment) {
+                    // depth test and color write in one atomic step, see Framebuffer::resolve
+                    let packed = pack_depth_color(pixel.z, argb_from_vec4(rgba));
+                    z_buffer[row + x].fetch_min(packed, std::sync::atomic::Ordering::Relaxed);
                 }
             }
         }
+        edges.next_row();
     }
 
 }
 
 
-pub fn raster_triangle_wireframe(
-    vertices: &[&Vertex; 3],
-    mvp: &Mat4,
+pub fn raster_triangle_wireframe<V: Varyings>(
+    vertices: &[TransformedVertex<V>; 3],
     buffer: &[AtomicU32],
     viewport_size: Vec2,
+    tile: &Tile,
     color: u32
 ) {
-    let clip0 = *mvp * vertices[0].position;
-    let clip1 = *mvp * vertices[1].position;
-    let clip2 = *mvp * vertices[2].position;
-
-    let rec0 = 1.0 / clip0.w;
-    let rec1 = 1.0 / clip1.w;
-    let rec2 = 1.0 / clip2.w;
-
-    // This would be the output of the vertex shader (clip space)
-    // then we perform perspective division to transform in ndc
-    // now x,y,z componend of ndc are between -1 and 1
-    let ndc0 = clip0 * rec0;
-    let ndc1 = clip1 * rec1;
-    let ndc2 = clip2 * rec2;
-
-    // screeen coordinates remapped to window
-    let sc0 = glam::vec2(
-    map_to_range(ndc0.x, -1.0, 1.0, 0.0, viewport_size.x),
-    map_to_range(ndc0.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-    let sc1 = glam::vec2(
-        map_to_range(ndc1.x, -1.0, 1.0, 0.0, viewport_size.x),
-        map_to_range(ndc1.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-    let sc2 = glam::vec2(
-        map_to_range(ndc2.x, -1.0, 1.0, 0.0, viewport_size.x),
-        map_to_range(ndc2.y, -1.0, 1.0, 0.0, viewport_size.y),
-    );
-
-    bresenham_line(buffer, color, sc0.x, sc0.y, sc1.x, sc1.y);
-    bresenham_line(buffer, color,sc0.x, sc0.y, sc2.x, sc2.y);
-    bresenham_line(buffer, color, sc2.x, sc2.y, sc1.x, sc1.y);
+    // outline of the clipped polygon, edges cut by the frustum end on its border
+    let polygon = clip_triangle(&vertices.map(|v| v.output));
+    let screen: Vec<Vec2> = polygon
+        .iter()
+        .map(|v| clip_to_screen(v.position, viewport_size))
+        .collect();
+
+    for i in 0..screen.len() {
+        let a = screen[i];
+        let b = screen[(i + 1) % screen.len()];
+        bresenham_line_in_rect(buffer, viewport_size, tile, color, a, b);
+    }
 
 }
 
@@ -372,162 +639,280 @@ pub struct Tile{
     pub max_y: i32,
 }
 
+impl Tile {
+    // One tile covering the whole viewport, for rasterizing without binning.
+    pub fn from_viewport(viewport_size: Vec2) -> Self {
+        Self {
+            min_x: 0,
+            min_y: 0,
+            max_x: viewport_size.x as i32,
+            max_y: viewport_size.y as i32,
+        }
+    }
+}
+
 // Bin: collection of triangles inside one tile
+// as (draw call, triangle) pairs, in the order they were submitted
 pub struct Bin{
-    pub triangle_indices: Vec<i32>
+    pub triangle_indices: Vec<(u32, u32)>
 }
 
-pub struct Setup{
+// One mesh with the shaders to draw it. Every draw call of a frame shares the shader types,
+// so a whole scene is binned and rasterized in one pass.
+pub struct DrawCall<'a, VS, FS> {
+    pub mesh: &'a MeshRenderer,
+    pub vertex_shader: VS,
+    pub fragment_shader: FS,
+}
+
+/// Framebuffer split into tiles with a bin each, row major: tile (x, y) has id y * columns + x.
+/// Tiles on the right and bottom edge are clamped to the framebuffer, so they can be smaller.
+/// Kept by the Framebuffer and reused every frame, binning only clears the bins.
+///
+/// ```
+/// use ruster::geometry::TileGrid;
+///
+/// let grid = TileGrid::new(100, 50, 32, 16);
+/// assert_eq!((grid.columns, grid.rows), (4, 4));
+/// let last = &grid.tiles[grid.id(3, 3)];
+/// assert_eq!((last.min_x, last.min_y, last.max_x, last.max_y), (96, 48, 100, 50));
+/// ```
+pub struct TileGrid {
+    pub width: usize,
+    pub height: usize,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub columns: usize,
+    pub rows: usize,
     pub tiles: Vec<Tile>,
     pub bins: Vec<Bin>,
+    // columns and rows of tiles each triangle of the last frame overlaps, in submission order
+    triangle_tiles: Vec<(Range<usize>, Range<usize>)>,
+    // triangles of the last frame overlapping each row of tiles, row after row in submission order,
+    // row_start[y]..row_start[y + 1] being those of row y
+    row_triangles: Vec<usize>,
+    row_start: Vec<usize>,
 }
 
-// ASK about structuring you framebuffer in a morton order for that simd and better chaches reads when sampling textures
+impl TileGrid {
+    pub const DEFAULT_TILE_SIZE: usize = 64;
+
+    pub fn new(width: usize, height: usize, tile_width: usize, tile_height: usize) -> Self {
+        let (tile_width, tile_height) = (tile_width.max(1), tile_height.max(1));
+        let columns = width.div_ceil(tile_width);
+        let rows = height.div_ceil(tile_height);
+
+        let mut tiles = Vec::with_capacity(columns * rows);
+        for y in 0..rows {
+            for x in 0..columns {
+                tiles.push(Tile {
+                    min_x: (x * tile_width) as i32,
+                    min_y: (y * tile_height) as i32,
+                    max_x: ((x + 1) * tile_width).min(width) as i32,
+                    max_y: ((y + 1) * tile_height).min(height) as i32,
+                });
+            }
+        }
+        let bins = (0..tiles.len()).map(|_| Bin { triangle_indices: Vec::new() }).collect();
 
-// Populates all the tiles from the setup
-// Filter the rendering based on bins and triangles.
-pub fn setup_tiles(framebuffer_width: f32, framebuffer_height: f32, tile_size: i32) -> Setup{
-    // Split the screen in tiles
-    // ASK: Tile size should be like the aspect ratio for max gains? 
-    //      Or different width height also possible?
-    let number_tiles_horizontal = ceil(framebuffer_width / tile_size as f32);
-    let number_tiles_vertical = ceil(framebuffer_height / tile_size as f32);
+        Self { width, height, tile_width, tile_height, columns, rows, tiles, bins, triangle_tiles: Vec::new(), row_triangles: Vec::new(), row_start: Vec::new() }
+    }
 
-    let mut tiles: Vec<Tile> = Vec::with_capacity(framebuffer_width as usize * framebuffer_height as usize);
-    let mut bins: Vec<Bin> = Vec::with_capacity(framebuffer_width as usize * framebuffer_height as usize);
+    pub fn id(&self, x: usize, y: usize) -> usize {
+        y * self.columns + x
+    }
 
-    for i in 0..number_tiles_horizontal as i32
-    {
-        for j in 0..number_tiles_vertical as i32
-        {
-            let id = i as f32 * number_tiles_horizontal + j as f32;
-            let tile = Tile{..Default::default()};
-            tiles.push(tile);
-            bins.push(Bin { triangle_indices: Vec::new() }); // empty bins
-            tiles[id as usize].min_x = i * tile_size;
-            tiles[id as usize].min_y = j * tile_size;
-            tiles[id as usize].max_x = (i + 1) * tile_size;
-            tiles[id as usize].max_y = (j + 1) * tile_size;
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    // Empties every bin, keeping their allocations for the next frame.
+    pub fn clear_bins(&mut self) {
+        for bin in self.bins.iter_mut() {
+            bin.triangle_indices.clear();
         }
     }
-    
-    Setup{tiles:tiles, bins:bins}
+
+    // Columns and rows of the tiles a screen space box overlaps, empty when it is off screen.
+    pub fn overlapping(&self, min: Vec2, max: Vec2) -> (Range<usize>, Range<usize>) {
+        // with floor give me the first tile and include it in the loop
+        // with ceil i say give me one past the last tile, but it's excluded cause the for loop is .. not ..=
+        // clamped to the grid, boxes partially off screen only land in visible tiles
+        let (tile_width, tile_height) = (self.tile_width as f32, self.tile_height as f32);
+        let min_x = (min.x / tile_width).floor().max(0.0) as usize;
+        let max_x = ((max.x / tile_width).ceil().max(0.0) as usize).min(self.columns);
+        let min_y = (min.y / tile_height).floor().max(0.0) as usize;
+        let max_y = ((max.y / tile_height).ceil().max(0.0) as usize).min(self.rows);
+        (min_x..max_x, min_y..max_y)
+    }
 }
 
-pub fn bin_triangle(setup: &mut Setup, bin_id: usize, tri_id: i32)
+// Triangles one task of the first binning pass walks.
+const BIN_CHUNK: usize = 4096;
+
+pub fn bin_triangle(bins: &mut [Bin], bin_id: usize, draw_id: u32, tri_id: u32)
 {
-    setup.bins[bin_id].triangle_indices.push(tri_id); 
+    bins[bin_id].triangle_indices.push((draw_id, tri_id)); 
 }
 
-// Populated the bins from setup
-// Go through each triangle and 
-pub fn bin_triangles(mesh: &MeshRenderer, setup: &mut Setup, mvp: &Mat4, tile_size: i32, number_tiles_horizontal: f32){
+// Screen space box around the visible part of a triangle, None when it is entirely outside one of the frustum planes.
+pub fn screen_bounds<V: Varyings>(vertices: &[TransformedVertex<V>; 3], viewport_size: Vec2) -> Option<(Vec2, Vec2)> {
+    if vertices.iter().fold(u8::MAX, |code, v| code & v.outcode) != 0 {
+        return None;
+    }
 
-    // coolest Rust out there this iterator loop
-    for (tri_id, triangle) in mesh.triangles().iter().enumerate() {
-        // find out over which bins this triangle is
-        // I loop over those where I call bin triangle so save the triangle in that bin
+    let mut min = Vec2::INFINITY;
+    let mut max = Vec2::NEG_INFINITY;
+    if vertices.iter().all(|v| v.outcode == 0) {
+        for v in vertices.iter() {
+            let screen = from_fixed(v.screen);
+            min = min.min(screen);
+            max = max.max(screen);
+        }
+    } else {
+        // crossing the frustum gets clipped first, so the box only covers the visible part of the triangle
+        for v in clip_triangle(&vertices.map(|v| v.output)).iter() {
+            let screen = clip_to_screen(v.position, viewport_size);
+            min = min.min(screen);
+            max = max.max(screen);
+        }
+    }
+    (min.x <= max.x).then_some((min, max))
+}
 
-        // ABB of tri
-        let vertices = mesh.get_vertices_from_triangle(*triangle);
+// Populated the bins of the grid, whatever they held from the last frame is dropped
+// First, chunks of triangles find the tiles each of them overlaps in parallel. The triangles are then
+// sorted by row of tiles, counting them per row, summing the counts into offsets and scattering them
+// in submission order. Last every row of tiles fills its own bins walking only its triangles, so every
+// bin lists them in the order they were submitted and overlapping triangles at equal depth resolve the
+// same way every frame. All of it writes into storage the grid keeps, nothing is allocated per chunk or per triangle
+pub fn bin_triangles<V: Varyings, FS: Sync>(draws: &[TransformedDraw<V, FS>], grid: &mut TileGrid, viewport_size: Vec2){
+
+    grid.clear_bins();
+
+    // where each draw starts in the list of all triangles, plus the total at the end
+    let mut first_triangle = Vec::with_capacity(draws.len() + 1);
+    first_triangle.push(0);
+    for draw in draws.iter() {
+        first_triangle.push(first_triangle.last().unwrap() + draw.mesh.triangles().len());
+    }
+    let total_triangles = *first_triangle.last().unwrap();
 
-        let clip0 = *mvp * vertices[0].position;
-        let clip1 = *mvp * vertices[1].position;
-        let clip2 = *mvp * vertices[2].position;
-
-        let rec0 = 1.0 / clip0.w;
-        let rec1 = 1.0 / clip1.w;
-        let rec2 = 1.0 / clip2.w;
-
-        // This would be the output of the vertex shader (clip space)
-        // then we perform perspective division to transform in ndc
-        // now x,y,z componend of ndc are between -1 and 1
-        let ndc0 = clip0 * rec0;
-        let ndc1 = clip1 * rec1;     
-        let ndc2 = clip2 * rec2;
-
-        // screeen coordinates remapped to window
-        let sc0 = glam::vec2(
-        map_to_range(ndc0.x, -1.0, 1.0, 0.0, SCREEN_WIDTH as f32),
-        map_to_range(ndc0.y, -1.0, 1.0, 0.0, SCREEN_HEIGHT as f32),
-        );
-        let sc1 = glam::vec2(
-            map_to_range(ndc1.x, -1.0, 1.0, 0.0, SCREEN_WIDTH as f32),
-            map_to_range(ndc1.y, -1.0, 1.0, 0.0, SCREEN_HEIGHT as f32),
-        );
-        let sc2 = glam::vec2(
-            map_to_range(ndc2.x, -1.0, 1.0, 0.0, SCREEN_WIDTH as f32),
-            map_to_range(ndc2.y, -1.0, 1.0, 0.0, SCREEN_HEIGHT as f32),
-        );
-
-        // aabb in screen space
-        let min_x = sc0.x.min(sc1.x).min(sc2.x);
-        let min_y = sc0.y.min(sc1.y).min(sc2.y);
-        let max_x = sc0.x.max(sc1.x).max(sc2.x);
-        let max_y = sc0.y.max(sc1.y).max(sc2.y);
-        
-        // to which bin it belongs to
-        // with floor give me the last tile and include it in the loop
-        // with ceil i say give me one past the last tile, but it's excluded cause the for loop is .. not ..=
-        // it can be both with floor but then i say ..=
+    let mut triangle_tiles = std::mem::take(&mut grid.triangle_tiles);
+    triangle_tiles.clear();
+    triangle_tiles.resize(total_triangles, (0..0, 0..0));
 
-        let tile_min_x = floor(min_x / tile_size as f32);
-        let tile_max_x = ceil(max_x / tile_size as f32);
-        let tile_min_y = floor(min_y / tile_size as f32);
-        let tile_max_y = ceil(max_y / tile_size as f32);
+    let grid_ref = &*grid;
+    triangle_tiles.par_chunks_mut(BIN_CHUNK).enumerate().for_each(|(chunk, tiles)| {
+        let start = chunk * BIN_CHUNK;
 
-        // we determined whikl tiles the triangle aabb overlaps. by dividing the aabb coords
-        // by tile size i get tile indices.
-        // a triangle can span multiple tiles so I iterate over all tiles in the range
+        // last draw starting at or before the chunk, empty draws are stepped over below
+        let mut draw_id = first_triangle.partition_point(|&first| first <= start) - 1;
+        for (index, tiles) in (start..).zip(tiles.iter_mut()) {
+            while index >= first_triangle[draw_id + 1] {
+                draw_id += 1;
+            }
+            let draw = &draws[draw_id];
+            let vertices = transformed_triangle(&draw.vertices, draw.mesh.triangles()[index - first_triangle[draw_id]]);
 
-        for tile_y in tile_min_y as i32..tile_max_y as i32
-        {
-            for tile_x in tile_min_x as i32..tile_max_x as i32
-            {
-                let bin_id = tile_y * number_tiles_horizontal as i32 + tile_x;
-                // bin it for those
-                bin_triangle(setup, bin_id.try_into().unwrap(), tri_id as i32);
+            // we determined which tiles the triangle aabb overlaps. by dividing the aabb coords
+            // by tile size i get tile indices, a triangle can span multiple tiles
+            if let Some((min, max)) = screen_bounds(&vertices, viewport_size) {
+                *tiles = grid_ref.overlapping(min, max);
             }
         }
+    });
 
-        
+    // count the triangles of every row, offset rows by the counts before them, then scatter
+    let rows = grid.rows;
+    let mut row_start = std::mem::take(&mut grid.row_start);
+    row_start.clear();
+    row_start.resize(rows + 2, 0);
+    for (_, tile_rows) in triangle_tiles.iter() {
+        for tile_y in tile_rows.clone() {
+            row_start[tile_y + 2] += 1;
+        }
     }
+    for tile_y in 2..row_start.len() {
+        row_start[tile_y] += row_start[tile_y - 1];
+    }
+    // row_start[y + 1] is where the next triangle of row y goes, ending up at the start of row y + 1
+    let mut row_triangles = std::mem::take(&mut grid.row_triangles);
+    row_triangles.clear();
+    row_triangles.resize(row_start[rows + 1], 0);
+    for (index, (_, tile_rows)) in triangle_tiles.iter().enumerate() {
+        for tile_y in tile_rows.clone() {
+            row_triangles[row_start[tile_y + 1]] = index;
+            row_start[tile_y + 1] += 1;
+        }
+    }
+
+    // each row of tiles only touches its own bins, no merging needed afterwards
+    let columns = grid.columns.max(1);
+    grid.bins.par_chunks_mut(columns).enumerate().for_each(|(tile_y, bins)| {
+        let mut draw_id = 0;
+        for &index in &row_triangles[row_start[tile_y]..row_start[tile_y + 1]] {
+            while index >= first_triangle[draw_id + 1] {
+                draw_id += 1;
+            }
+            let tri_id = index - first_triangle[draw_id];
+            for tile_x in triangle_tiles[index].0.clone() {
+                bin_triangle(bins, tile_x, draw_id as u32, tri_id as u32);
+            }
+        }
+    });
+
+    grid.triangle_tiles = triangle_tiles;
+    grid.row_triangles = row_triangles;
+    grid.row_start = row_start;
 }
 
 // Method 1: Iterate over all triangles from mesh and rasterize.
-pub fn raster_mesh(
+pub fn raster_mesh<VS, FS>(
     mesh: &MeshRenderer,
-    mvp: &Mat4,
-    texture: Option<&Texture>,
-    buffer: &[AtomicU32],
-    z_buffer: &[AtomicU32],
-    viewport_size: Vec2,
-) {
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+    framebuffer: &Framebuffer,
+) where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
+{
+    let screen = Tile::from_viewport(framebuffer.size());
     for triangle in mesh.triangles() {
         let vertices = mesh.get_vertices_from_triangle(*triangle);
-        raster_triangle(&vertices, mvp, texture, buffer, z_buffer, viewport_size);
+        raster_triangle(&vertices, vertex_shader, fragment_shader, &framebuffer.z_buffer, framebuffer.size(), &screen);
     }
+    framebuffer.resolve();
 }
 
 // Method 2: Bin triangles from mesh into tiles. Rasterize tiles on multiple threads.
-pub fn render_tile(
-    setup: &Setup, 
+pub fn render_tile<V, FS>(
+    grid: &TileGrid, 
     bin_id: usize, 
-    mesh: &MeshRenderer,
-    mvp: &Mat4, 
-    texture: Option<&Texture>, 
+    draws: &[TransformedDraw<V, FS>],
     buffer: &[AtomicU32],
-    z_buffer: &[AtomicU32],
+    z_buffer: &[AtomicU64],
     viewport_size: Vec2,
-    wireframe: bool){
+    wireframe: bool)
+where
+    V: Varyings,
+    FS: FragmentShader<V>,
+{
     // this is the functions that will run on multiple threads
 
-    let bin = &setup.bins[bin_id as usize];
+    let bin = &grid.bins[bin_id];
+    let tile = &grid.tiles[bin_id];
 
     for tri_index in 0..bin.triangle_indices.len()
     {
-        let triangle = mesh.triangles()[bin.triangle_indices[tri_index] as usize];
-        let vertices = mesh.get_vertices_from_triangle(triangle);
+        let (draw_id, triangle_id) = bin.triangle_indices[tri_index];
+        let draw = &draws[draw_id as usize];
+        let triangle = draw.mesh.triangles()[triangle_id as usize];
+        let vertices = transformed_triangle(&draw.vertices, triangle);
         if wireframe
         {
                 let mut rng = StdRng::seed_from_u64(bin_id as u64);
@@ -535,63 +920,450 @@ pub fn render_tile(
                 let g = rng.random_range(0..255) as u8;
                 let b = rng.random_range(0..255) as u8;
                 let color = to_argb(255, r, g, b);
-                raster_triangle_wireframe(&vertices, mvp, buffer, viewport_size, color);
+                raster_triangle_wireframe(&vertices, buffer, viewport_size, tile, color);
         }
         else
         {
-            raster_triangle(&vertices, mvp, texture, buffer, z_buffer, viewport_size);
+            raster_transformed_triangle(&vertices, draw.fragment_shader, z_buffer, viewport_size, tile);
         }
     }
 }
 
-pub fn render_scene(
+// Draws one mesh with the given shaders, see shader.rs for the built in ones.
+pub fn render_mesh<VS, FS>(
     mesh: &MeshRenderer,
-    mvp: &Mat4,
-    texture: Option<&Texture>,
-    buffer: &[AtomicU32],
-    z_buffer: &[AtomicU32],
-    viewport_size: Vec2,
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+    framebuffer: &Framebuffer,
     wireframe: bool)
+where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
 {
-    // create and populate tiles with aabb from grid
-    let tile_size = 64;
-    let mut scene_setup = setup_tiles(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32, tile_size); // ASK ABOUT THE SIZE, look in setup
+    render_draws(&[DrawCall { mesh, vertex_shader, fragment_shader }], framebuffer, wireframe);
+}
 
-    let number_tiles_horizontal = ceil(SCREEN_WIDTH as f32 / tile_size as f32);
-    let number_tiles_vertical = ceil(SCREEN_HEIGHT as f32 / tile_size as f32);
+// Draws every draw call into the framebuffer, all binned together so they share
+// one pass over the tiles and one depth buffer.
+pub fn render_draws<VS, FS>(
+    draws: &[DrawCall<VS, FS>],
+    framebuffer: &Framebuffer,
+    wireframe: bool)
+where
+    VS: VertexShader,
+    FS: FragmentShader<VS::Varyings>,
+{
+    let viewport_size = framebuffer.size();
+    let buffer = &framebuffer.buffer[..];
+    let z_buffer = &framebuffer.z_buffer[..];
+
+    // the framebuffer's tiles, kept from the last frame. Nothing else renders into it meanwhile,
+    // a panic in an earlier frame leaves nothing in the grid that binning doesn't clear
+    let mut grid = framebuffer.tile_grid.lock().unwrap_or_else(PoisonError::into_inner);
+
+    // vertex stage, read by both the binning and every tile a triangle lands in
+    let draws: Vec<_> = draws
+        .iter()
+        .map(|draw| TransformedDraw {
+            mesh: draw.mesh,
+            fragment_shader: &draw.fragment_shader,
+            vertices: transform_vertices(draw.mesh, &draw.vertex_shader, viewport_size),
+        })
+        .collect();
+    let draws = &draws[..];
 
     // populate bins with tris
-    bin_triangles(mesh, &mut scene_setup, mvp, tile_size, number_tiles_horizontal);
+    bin_triangles(draws, &mut grid, viewport_size);
 
-    let total_tiles = number_tiles_horizontal * number_tiles_vertical;
-    let scene_setup = &scene_setup; 
+    let grid = &*grid;
 
-    // BEFORE no rayon crate, one thread per tile
-    /*
-    // Render tris
-    std::thread::scope(|s| {
-        for tile in 0..total_tiles as i32 {
-            s.spawn(move || {
-                render_tile(scene_setup,tile as usize, mesh, mvp, texture,  buffer, z_buffer, viewport_size, wireframe);
-            });
-        }
+    (0..grid.len()).into_par_iter().for_each(|tile| {
+        render_tile(grid, tile, draws, buffer, z_buffer, viewport_size, wireframe);
     });
-    */
 
-    std::thread::scope(|s| {
-        (0..total_tiles as i32).into_par_iter().for_each(|tile| {
-            render_tile(scene_setup, tile as usize, mesh, mvp, texture, buffer, z_buffer, viewport_size, wireframe);
-        });
-    });
+    // raster_triangle only writes the packed depth buffer, move the colors over
+    if !wireframe
+    {
+        framebuffer.resolve();
+    }
 
     if wireframe
     {
         // Render lines
-        for j in 0..number_tiles_horizontal as i32
+        let color = to_argb(255, 255, 255, 255); 
+        for j in 0..grid.columns
+        {
+            let x = (j * grid.tile_width) as f32;
+            bresenham_line(buffer, viewport_size, color, x, 0.0, x, viewport_size.y);
+        }
+        for j in 0..grid.rows
         {
-            let color = to_argb(255, 255, 255, 255); 
-            bresenham_line(buffer, color, j as f32 * tile_size as f32, 0 as f32, j as f32 * tile_size as f32, SCREEN_HEIGHT as f32);
-            bresenham_line(buffer, color, 0 as f32, j as f32 * tile_size as f32, SCREEN_WIDTH as f32, j as f32 * tile_size as f32 as f32);
+            let y = (j * grid.tile_height) as f32;
+            bresenham_line(buffer, viewport_size, color, 0.0, y, viewport_size.x, y);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Mat4, vec2, vec4};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const IDENTITY: TransformShader = TransformShader { mvp: Mat4::IDENTITY };
+    const VERTEX_COLOR: TextureShader = TextureShader { texture: None, base_color: Vec4::ONE };
+
+    // Vertex at a screen position (y down) of a `size` x `size` framebuffer, through an identity mvp.
+    fn at_screen(point: Vec2, size: usize) -> Vertex {
+        let size = size as f32;
+        let ndc = vec2(point.x / size * 2.0 - 1.0, 1.0 - point.y / size * 2.0);
+        Vertex::new(ndc.extend(0.5).extend(1.0), Vec3::Z, Vec3::ONE, Vec2::ZERO)
+    }
+
+    // How many of the triangles, each drawn on its own, wrote every pixel.
+    fn write_counts(triangles: &[[Vec2; 3]], size: usize) -> Vec<u32> {
+        let mut counts = vec![0; size * size];
+        for triangle in triangles {
+            let pixels = covered(&triangle.map(|p| at_screen(p, size)), size);
+            for (count, written) in counts.iter_mut().zip(pixels) {
+                *count += written as u32;
+            }
+        }
+        counts
+    }
+
+    // Pixels the triangles, given as consecutive vertices, wrote through an identity mvp.
+    fn covered(vertices: &[Vertex], size: usize) -> Vec<bool> {
+        let triangles: Vec<UVec3> = (0..vertices.len() as u32 / 3).map(|i| UVec3::new(i * 3, i * 3 + 1, i * 3 + 2)).collect();
+        let mesh = MeshRenderer::from_vertices(&triangles, vertices);
+        let framebuffer = Framebuffer::new(size, size);
+        render_mesh(&mesh, &IDENTITY, &VERTEX_COLOR, &framebuffer, false);
+        framebuffer.z_buffer.iter().map(|z| z.load(Ordering::Relaxed) != CLEAR_DEPTH).collect()
+    }
+
+    // Two triangles covering the whole viewport, wound to face the camera.
+    fn full_screen_quad() -> MeshRenderer {
+        let corners = [vec4(-1.0, -1.0, 0.5, 1.0), vec4(1.0, -1.0, 0.5, 1.0), vec4(1.0, 1.0, 0.5, 1.0), vec4(-1.0, 1.0, 0.5, 1.0)];
+        let vertices = corners.map(|p| Vertex::new(p, Vec3::Z, Vec3::ONE, Vec2::ZERO));
+        MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)], &vertices)
+    }
+
+    #[test]
+    fn render_scene_draws_without_a_window() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let vertices = [
+            Vertex::new(vec4(-0.5, -0.5, 0.5, 1.0), Vec3::Z, red, Vec2::ZERO),
+            Vertex::new(vec4(0.5, -0.5, 0.5, 1.0), Vec3::Z, red, Vec2::ZERO),
+            Vertex::new(vec4(0.0, 0.5, 0.5, 1.0), Vec3::Z, red, Vec2::ZERO),
+        ];
+        let mesh = MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2)], &vertices);
+        // not a multiple of the tile size either way
+        let framebuffer = Framebuffer::new(300, 100);
+
+        render_mesh(&mesh, &IDENTITY, &VERTEX_COLOR, &framebuffer, false);
+
+        let center = coords_to_index(150, 50, framebuffer.width);
+        let color = framebuffer.buffer[center].load(Ordering::Relaxed);
+        assert_eq!(color & 0xff00ffff, to_argb(255, 0, 0, 0));
+        assert!(color >> 16 & 0xff >= 254);
+        assert!(unpack_depth(framebuffer.z_buffer[center].load(Ordering::Relaxed)) < f32::INFINITY);
+        // the triangle spans half the viewport in ndc, whatever its resolution
+        assert_eq!(framebuffer.buffer[coords_to_index(100, 50, 300)].load(Ordering::Relaxed), 0);
+        assert_ne!(framebuffer.buffer[coords_to_index(120, 50, 300)].load(Ordering::Relaxed), 0);
+    }
+
+    // Floor triangle running from in front of the camera to behind it (w < 0 after projection).
+    fn through_the_near_plane() -> (MeshRenderer, Mat4) {
+        let white = Vec3::ONE;
+        let vertices = [
+            Vertex::new(vec4(-1.0, -0.5, -3.0, 1.0), Vec3::Y, white, Vec2::ZERO),
+            Vertex::new(vec4(0.0, -0.5, 2.0, 1.0), Vec3::Y, white, Vec2::ZERO),
+            Vertex::new(vec4(1.0, -0.5, -3.0, 1.0), Vec3::Y, white, Vec2::ZERO),
+        ];
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        (MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2)], &vertices), projection)
+    }
+
+    #[test]
+    fn triangles_through_the_near_plane_are_clipped() {
+        let (mesh, mvp) = through_the_near_plane();
+        let framebuffer = Framebuffer::new(256, 256);
+        render_mesh(&mesh, &TransformShader { mvp }, &VERTEX_COLOR, &framebuffer, false);
+
+        // the far edge is at ndc y = -1/6, row 149, and the floor runs into the bottom of the screen
+        let drawn = |x: usize, y: usize| framebuffer.buffer[coords_to_index(x, y, 256)].load(Ordering::Relaxed) != 0;
+        assert!(drawn(0, 255) && drawn(255, 255) && drawn(128, 155));
+        assert!(!drawn(60, 155) && !drawn(196, 155));
+        assert!((0..146).all(|y| (0..256).all(|x| !drawn(x, y))));
+    }
+
+    #[test]
+    fn clipped_triangles_are_binned_by_their_visible_part() {
+        let (mesh, mvp) = through_the_near_plane();
+        let viewport = glam::vec2(256.0, 256.0);
+        let mut grid = TileGrid::new(256, 256, 64, 64);
+        let vertices = transform_vertices(&mesh, &TransformShader { mvp }, viewport);
+        let draws = [TransformedDraw { mesh: &mesh, fragment_shader: &VERTEX_COLOR, vertices }];
+        bin_triangles(&draws, &mut grid, viewport);
+
+        // tile rows 2 and 3 only, the vertex behind the camera doesn't stretch the bounds
+        let binned: Vec<bool> = grid.bins.iter().map(|bin| !bin.triangle_indices.is_empty()).collect();
+        assert_eq!(binned[..8], [false; 8]);
+        assert_eq!(binned[8..], [true; 8]);
+    }
+
+    #[test]
+    fn draw_calls_share_one_depth_buffer() {
+        // a red quad in front of a green one, the red one submitted last
+        let quad = |z: f32, color: Vec3| {
+            let corners = [vec4(-1.0, -1.0, z, 1.0), vec4(1.0, -1.0, z, 1.0), vec4(1.0, 1.0, z, 1.0), vec4(-1.0, 1.0, z, 1.0)];
+            MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)], &corners.map(|p| Vertex::new(p, Vec3::Z, color, Vec2::ZERO)))
+        };
+        let (near, far) = (quad(0.25, Vec3::X), quad(0.75, Vec3::Y));
+        let draw = |mesh| DrawCall { mesh, vertex_shader: IDENTITY, fragment_shader: VERTEX_COLOR };
+
+        for draws in [[draw(&far), draw(&near)], [draw(&near), draw(&far)]] {
+            let framebuffer = Framebuffer::new(100, 100);
+            render_draws(&draws, &framebuffer, false);
+            let pixels: Vec<u32> = framebuffer.buffer.iter().map(|p| p.load(Ordering::Relaxed)).collect();
+            assert!(pixels.iter().all(|&p| p >> 16 & 0xff >= 254 && p & 0xffff == 0), "the order draws are submitted in doesn't matter");
+        }
+    }
+
+    // Counts the vertices it shades, drawing them like IDENTITY.
+    struct CountingShader(AtomicUsize);
+
+    impl VertexShader for CountingShader {
+        type Varyings = Vertex;
+
+        fn shade(&self, vertex: &Vertex) -> VertexOutput<Vertex> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            IDENTITY.shade(vertex)
+        }
+    }
+
+    #[test]
+    fn vertices_are_shaded_once_per_frame() {
+        // the quad's two triangles share a diagonal and are binned into every tile
+        let shader = CountingShader(AtomicUsize::new(0));
+        let framebuffer = Framebuffer::new(300, 200);
+        render_mesh(&full_screen_quad(), &shader, &VERTEX_COLOR, &framebuffer, false);
+        assert_eq!(shader.0.load(Ordering::Relaxed), 4);
+        assert!(framebuffer.buffer.iter().all(|pixel| pixel.load(Ordering::Relaxed) != 0));
+    }
+
+    #[test]
+    fn parallel_binning_keeps_submission_order() {
+        // small triangles scattered over the screen and past its edges, over several chunks and draws
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut meshes = Vec::new();
+        for count in [BIN_CHUNK + 1000, 10, 0, BIN_CHUNK * 2 + 7] {
+            let vertices: Vec<Vertex> = (0..count * 3)
+                .map(|_| {
+                    let p = vec2(rng.random_range(-1.2..1.2), rng.random_range(-1.2..1.2));
+                    Vertex::new(p.extend(rng.random_range(-0.5..1.5)).extend(1.0), Vec3::Z, Vec3::ONE, Vec2::ZERO)
+                })
+                .collect();
+            let triangles: Vec<UVec3> = (0..count as u32).map(|i| UVec3::new(i * 3, i * 3 + 1, i * 3 + 2)).collect();
+            meshes.push(MeshRenderer::from_vertices(&triangles, &vertices));
+        }
+
+        let viewport_size = vec2(256.0, 192.0);
+        let draws: Vec<_> = meshes
+            .iter()
+            .map(|mesh| TransformedDraw { mesh, fragment_shader: &VERTEX_COLOR, vertices: transform_vertices(mesh, &IDENTITY, viewport_size) })
+            .collect();
+
+        let mut grid = TileGrid::new(256, 192, 32, 48);
+        bin_triangles(&draws, &mut grid, viewport_size);
+
+        // the same bins filled one triangle after the other
+        let single = MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2)], &[Vertex::new(Vec4::W, Vec3::Z, Vec3::ONE, Vec2::ZERO); 3]);
+        let mut serial: Vec<Vec<(u32, u32)>> = vec![Vec::new(); grid.len()];
+        let mut one = TileGrid::new(256, 192, 32, 48);
+        for (draw_id, draw) in draws.iter().enumerate() {
+            for (tri_id, &triangle) in draw.mesh.triangles().iter().enumerate() {
+                let vertices = transformed_triangle(&draw.vertices, triangle).to_vec();
+                let alone = [TransformedDraw { mesh: &single, fragment_shader: &VERTEX_COLOR, vertices }];
+                bin_triangles(&alone, &mut one, viewport_size);
+                for (bin, expected) in one.bins.iter().zip(serial.iter_mut()) {
+                    if !bin.triangle_indices.is_empty() {
+                        expected.push((draw_id as u32, tri_id as u32));
+                    }
+                }
+            }
+        }
+
+        for (bin, expected) in grid.bins.iter().zip(serial.iter()) {
+            assert!(bin.triangle_indices.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(&bin.triangle_indices, expected);
+        }
+        // triangles from the later chunks did land in the bins
+        assert!(grid.bins.iter().any(|bin| bin.triangle_indices.iter().any(|&(draw, tri)| draw == 3 && tri as usize > BIN_CHUNK)));
+
+        // binning again into the same grid gives the same bins
+        bin_triangles(&draws, &mut grid, viewport_size);
+        assert!(grid.bins.iter().zip(serial.iter()).all(|(bin, expected)| &bin.triangle_indices == expected));
+    }
+
+    #[test]
+    fn rasterizing_for_a_tile_only_writes_its_pixels() {
+        // 5 x 2 tiles, the last column and row only partially on screen
+        let framebuffer = Framebuffer::new(317, 91);
+        let grid = TileGrid::new(317, 91, 64, 64);
+        let quad = full_screen_quad();
+        let corner = &grid.tiles[grid.id(4, 1)];
+        assert_eq!((corner.min_x, corner.min_y), (256, 64));
+
+        for triangle in quad.triangles() {
+            let vertices = quad.get_vertices_from_triangle(*triangle);
+            raster_triangle(&vertices, &IDENTITY, &VERTEX_COLOR, &framebuffer.z_buffer, framebuffer.size(), corner);
+        }
+        for (i, z) in framebuffer.z_buffer.iter().enumerate() {
+            let (x, y) = (i % 317, i / 317);
+            let inside = x >= 256 && y >= 64;
+            assert_eq!(z.load(Ordering::Relaxed) != CLEAR_DEPTH, inside, "pixel ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn partial_tiles_still_cover_the_screen_edges() {
+        let framebuffer = Framebuffer::new(317, 91);
+        render_mesh(&full_screen_quad(), &IDENTITY, &VERTEX_COLOR, &framebuffer, false);
+        assert!(framebuffer.buffer.iter().all(|pixel| pixel.load(Ordering::Relaxed) != 0));
+    }
+
+    #[test]
+    fn positive_ndc_y_is_the_top_row() {
+        // counter clockwise in ndc, the top left half of the upper half of the screen
+        let ndc = [vec2(-1.0, 0.0), vec2(1.0, 0.0), vec2(-1.0, 1.0)];
+        let vertices = ndc.map(|p| Vertex::new(p.extend(0.5).extend(1.0), Vec3::Z, Vec3::ONE, Vec2::ZERO));
+        let pixels = covered(&vertices, 8);
+
+        assert!(pixels[coords_to_index(0, 0, 8)]);
+        assert!(pixels[coords_to_index(0, 3, 8)]);
+        assert!((4..8).all(|y| (0..8).all(|x| !pixels[coords_to_index(x, y, 8)])));
+    }
+
+    #[test]
+    fn clockwise_triangles_are_culled() {
+        let front = [vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0)];
+        let back = [front[0], front[2], front[1]];
+        let to_vertex = |p: Vec2| Vertex::new(p.extend(0.5).extend(1.0), Vec3::Z, Vec3::ONE, Vec2::ZERO);
+
+        assert!(covered(&front.map(to_vertex), 8).iter().any(|&pixel| pixel));
+        assert!(covered(&back.map(to_vertex), 8).iter().all(|&pixel| !pixel));
+    }
+
+    #[test]
+    fn shared_edges_are_written_once() {
+        // a vertical edge through the pixel centers at x = 7.5, one triangle on each side
+        let (top, bottom) = (vec2(7.5, 0.5), vec2(7.5, 15.5));
+        let left = [top, vec2(1.0, 8.0), bottom];
+        let right = [top, bottom, vec2(14.0, 8.0)];
+        let counts = write_counts(&[left, right], 16);
+
+        assert!(counts.iter().all(|&count| count <= 1));
+        // every center on the shared edge between its ends belongs to exactly one of them. The ends are
+        // vertices on a right or bottom edge of both triangles, whatever lies around them gets those
+        assert!((1..15).all(|y| counts[coords_to_index(7, y, 16)] == 1));
+        // and the triangles don't leave a gap next to the edge either
+        assert!((2..14).all(|y| counts[coords_to_index(6, y, 16)] == 1 && counts[coords_to_index(8, y, 16)] == 1));
+    }
+
+    #[test]
+    fn quad_diagonal_is_written_once() {
+        // corners and the diagonal both run through pixel centers
+        let corners = [vec2(0.5, 0.5), vec2(15.5, 0.5), vec2(15.5, 15.5), vec2(0.5, 15.5)];
+        let lower = [corners[0], corners[3], corners[2]];
+        let upper = [corners[0], corners[2], corners[1]];
+        let counts = write_counts(&[lower, upper], 16);
+
+        assert!(counts.iter().all(|&count| count <= 1));
+        assert!((0..15).all(|i| counts[coords_to_index(i, i, 16)] == 1));
+        // top and left edges are in, right and bottom ones belong to the neighbours: 15 x 15 pixels, no holes
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(counts[coords_to_index(x, y, 16)], (x < 15 && y < 15) as u32, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    // ndc height of each vertex, shown as gray above the middle of the screen and discarded below
+    #[derive(Clone, Copy)]
+    struct Height {
+        y: f32,
+    }
+    crate::impl_varyings!(Height { y });
+
+    struct HeightShader;
+
+    impl VertexShader for HeightShader {
+        type Varyings = Height;
+
+        fn shade(&self, vertex: &Vertex) -> VertexOutput<Height> {
+            VertexOutput { position: vertex.position, varyings: Height { y: vertex.position.y } }
+        }
+    }
+
+    impl FragmentShader<Height> for HeightShader {
+        fn shade(&self, fragment: &Fragment<Height>) -> Option<Vec4> {
+            let y = fragment.varyings.y;
+            (y >= 0.0).then(|| Vec4::new(y, y, y, 1.0))
+        }
+    }
+
+    #[test]
+    fn custom_shaders_interpolate_their_varyings_and_discard() {
+        let framebuffer = Framebuffer::new(8, 8);
+        render_mesh(&full_screen_quad(), &HeightShader, &HeightShader, &framebuffer, false);
+
+        let pixel = |y: usize| framebuffer.buffer[coords_to_index(3, y, 8)].load(Ordering::Relaxed);
+        // pixel centers of the top rows sit at ndc y = 7/8, 5/8, ...
+        assert_eq!(pixel(0), argb_from_vec4(Vec4::new(0.875, 0.875, 0.875, 1.0)));
+        assert_eq!(pixel(3), argb_from_vec4(Vec4::new(0.125, 0.125, 0.125, 1.0)));
+        // discarded fragments don't write depth either
+        assert!((4..8).all(|y| framebuffer.z_buffer[coords_to_index(3, y, 8)].load(Ordering::Relaxed) == CLEAR_DEPTH));
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_tangent_and_its_sign() {
+        // the doc example's quad with the texture mirrored left to right
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y];
+        let uvs = [vec2(1.0, 1.0), vec2(0.0, 1.0), vec2(0.0, 0.0), vec2(1.0, 0.0)];
+        let mut triangles = [UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)];
+        let (tangents, copies) = generate_tangents(&mut triangles, &positions, &[Vec3::Z; 4], &uvs);
+        assert!(copies.is_empty());
+
+        // +u now runs along -x while the bitangent still follows +y
+        for tangent in tangents {
+            assert!(tangent.abs_diff_eq(vec4(-1.0, 0.0, 0.0, -1.0), 1e-5), "{}", tangent);
+            let bitangent = Vec3::Z.cross(tangent.truncate()) * tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+
+    #[test]
+    fn mirrored_uv_islands_keep_their_tangents() {
+        // two quads along x mapped with the same half of the texture, the right one mirrored in u
+        let positions: Vec<Vec3> = [0.0, 1.0, 2.0, 0.0, 1.0, 2.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| Vec3::new(x, (i / 3) as f32, 0.0))
+            .collect();
+        let uvs = [vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(0.0, 1.0), vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 0.0)];
+        let mut triangles = [UVec3::new(0, 1, 4), UVec3::new(0, 4, 3), UVec3::new(1, 2, 5), UVec3::new(1, 5, 4)];
+        let (tangents, copies) = generate_tangents(&mut triangles, &positions, &[Vec3::Z; 6], &uvs);
+
+        // the two vertices on the seam are split, the mirrored quad moves to the copies
+        assert_eq!(copies, vec![1, 4]);
+        assert_eq!(triangles[2], UVec3::new(6, 2, 5));
+        assert_eq!(triangles[3], UVec3::new(6, 5, 7));
+        for (i, tri) in triangles.iter().enumerate() {
+            let expected = if i < 2 { Vec4::new(1.0, 0.0, 0.0, 1.0) } else { Vec4::new(-1.0, 0.0, 0.0, -1.0) };
+            for corner in [tri.x, tri.y, tri.z] {
+                assert!(tangents[corner as usize].abs_diff_eq(expected, 1e-5), "triangle {i}: {}", tangents[corner as usize]);
+            }
+        }
+    }
+}
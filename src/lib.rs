@@ -0,0 +1,38 @@
+// Headless rasterizer library. The Bevy/egui viewer lives in `src/bin/viewer.rs`
+// behind the `viewer` feature so tools and tests can render without a window.
+
+pub mod camera;
+pub mod clipping;
+pub mod coverage;
+pub mod environment;
+pub mod error;
+pub mod framebuffer;
+pub mod geometry;
+pub mod light;
+pub mod material;
+pub mod pbr;
+pub mod sampler;
+pub mod scene;
+pub mod shader;
+pub mod shadow;
+pub mod texture;
+pub mod transform;
+pub mod utilities;
+pub mod window;
+
+#[cfg(test)]
+mod fixtures;
+
+pub use crate::camera::{RendererCamera, View};
+pub use crate::environment::Environment;
+pub use crate::error::{Error, Result};
+pub use crate::framebuffer::Framebuffer;
+pub use crate::geometry::{DrawCall, MeshRenderer, Vertex, render_draws, render_mesh};
+pub use crate::light::{Light, LightKind, Lighting};
+pub use crate::material::{Material, MaterialTextures};
+pub use crate::sampler::{Filter, Sampler, Wrap};
+pub use crate::scene::{Instance, Scene, SceneShader, Shading, render_scene, render_scene_with};
+pub use crate::shader::{FragmentShader, VertexShader};
+pub use crate::shadow::{ShadowMap, ShadowMaps};
+pub use crate::texture::Texture;
+pub use crate::transform::{Transform, TransformInitialParams};
@@ -0,0 +1,163 @@
+use crate::environment::Environment;
+use crate::shadow::ShadowMaps;
+use glam::Vec3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    // parallel rays along `direction`, position is ignored
+    Directional,
+    // radiates in every direction from `position`
+    Point,
+    // cone around `direction`, angles in radians from its axis, full intensity inside `inner_cone`
+    Spot { inner_cone: f32, outer_cone: f32 },
+}
+
+// Punctual light, same model as glTF's KHR_lights_punctual.
+// `direction` is where the light shines to, `range` is where point and spot lights fade out.
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    // renders a shadow map each frame, directional and spot lights only
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: Vec3::ZERO,
+            direction: direction.normalize_or_zero(),
+            color,
+            intensity,
+            range: f32::INFINITY,
+            cast_shadows: true,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: -Vec3::Y,
+            color,
+            intensity,
+            range,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, range: f32, inner_cone: f32, outer_cone: f32) -> Self {
+        Self {
+            kind: LightKind::Spot { inner_cone, outer_cone },
+            position,
+            direction: direction.normalize_or_zero(),
+            color,
+            intensity,
+            range,
+            cast_shadows: true,
+        }
+    }
+
+    /// Direction from `point` towards the light and the light arriving there,
+    /// after distance and cone falloff. None when the point gets no light at all.
+    ///
+    /// ```
+    /// use glam::Vec3;
+    /// use ruster::Light;
+    ///
+    /// let light = Light::point(Vec3::new(0.0, 2.0, 0.0), Vec3::ONE, 4.0, 10.0);
+    /// let (to_light, radiance) = light.incident(Vec3::ZERO).unwrap();
+    /// assert_eq!(to_light, Vec3::Y);
+    /// assert!((radiance.x - 1.0).abs() < 0.01);
+    /// assert!(light.incident(Vec3::new(0.0, 20.0, 0.0)).is_none());
+    /// ```
+    pub fn incident(&self, point: Vec3) -> Option<(Vec3, Vec3)> {
+        let radiance = self.color * self.intensity;
+        if self.kind == LightKind::Directional {
+            return Some((-self.direction.normalize_or_zero(), radiance));
+        }
+
+        let offset = self.position - point;
+        let distance_squared = offset.length_squared().max(1e-4);
+        let to_light = offset / distance_squared.sqrt();
+
+        // inverse square with a smooth window so the light reaches exactly zero at range
+        let window = (1.0 - (distance_squared / (self.range * self.range)).powi(2)).clamp(0.0, 1.0);
+        let mut attenuation = window * window / distance_squared;
+
+        if let LightKind::Spot { inner_cone, outer_cone } = self.kind {
+            let cos_angle = self.direction.normalize_or_zero().dot(-to_light);
+            let (cos_inner, cos_outer) = (inner_cone.cos(), outer_cone.cos());
+            let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-4)).clamp(0.0, 1.0);
+            attenuation *= t * t;
+        }
+
+        (attenuation > 0.0).then_some((to_light, radiance * attenuation))
+    }
+}
+
+// Blinn-Phong reflection of one light off a surface, all vectors normalized and pointing away from it.
+pub fn blinn_phong(normal: Vec3, to_light: Vec3, to_eye: Vec3, albedo: Vec3, specular: f32, shininess: f32) -> Vec3 {
+    let n_dot_l = normal.dot(to_light);
+    if n_dot_l <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let half = (to_light + to_eye).normalize_or_zero();
+    let highlight = normal.dot(half).max(0.0).powf(shininess);
+    albedo * n_dot_l + Vec3::splat(specular * highlight)
+}
+
+// Everything that lights a frame: punctual lights plus an optional environment for ambient light.
+#[derive(Clone, Copy, Default)]
+pub struct Lighting<'a> {
+    pub lights: &'a [Light],
+    pub environment: Option<&'a Environment>,
+    // shadow maps kept across frames, None renders them into maps allocated for the frame
+    pub shadows: Option<&'a ShadowMaps>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directional_lights_reach_everywhere_unattenuated() {
+        let light = Light::directional(Vec3::new(0.0, -2.0, 0.0), Vec3::new(1.0, 0.5, 0.0), 2.0);
+        for point in [Vec3::ZERO, Vec3::splat(1000.0)] {
+            assert_eq!(light.incident(point), Some((Vec3::Y, Vec3::new(2.0, 1.0, 0.0))));
+        }
+    }
+
+    #[test]
+    fn spot_lights_fade_between_their_cones() {
+        let cone = |degrees: f32| degrees.to_radians();
+        let light = Light::spot(Vec3::new(0.0, 1.0, 0.0), -Vec3::Y, Vec3::ONE, 1.0, 100.0, cone(20.0), cone(40.0));
+        // the floor point `degrees` off the cone axis, one unit below the light
+        let radiance = |degrees: f32| light.incident(Vec3::new(cone(degrees).tan(), 0.0, 0.0)).map(|(_, radiance)| radiance.x);
+
+        let on_axis = radiance(0.0).unwrap();
+        assert!((on_axis - 1.0).abs() < 1e-3, "{}", on_axis);
+        // inside the inner cone only distance matters
+        let inner = radiance(15.0).unwrap() * (1.0 + cone(15.0).tan().powi(2));
+        assert!((inner - on_axis).abs() < 1e-3, "{}", inner);
+        let between = radiance(30.0).unwrap();
+        assert!(between > 0.0 && between < radiance(15.0).unwrap());
+        assert!(radiance(45.0).is_none());
+    }
+
+    #[test]
+    fn blinn_phong_lights_the_front_side_only() {
+        let albedo = Vec3::new(0.5, 0.25, 1.0);
+        // light and eye straight above: full diffuse plus the whole highlight
+        assert_eq!(blinn_phong(Vec3::Y, Vec3::Y, Vec3::Y, albedo, 0.5, 32.0), albedo + 0.5);
+        assert_eq!(blinn_phong(Vec3::Y, -Vec3::Y, Vec3::Y, albedo, 0.5, 32.0), Vec3::ZERO);
+        // grazing light, the highlight is gone long before the diffuse
+        let grazing = blinn_phong(Vec3::Y, Vec3::new(1.0, 0.1, 0.0).normalize(), Vec3::Y, albedo, 0.5, 32.0);
+        assert!(grazing.x < 0.06 && grazing.x > 0.04, "{}", grazing);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::texture::Texture;
+use glam::{Vec3, Vec4};
+
+// glTF metallic-roughness material. Texture fields index into Scene::textures,
+// factors multiply whatever the texture holds (or stand alone without one).
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    // metalness in blue, roughness in green
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<usize>,
+}
+
+// Defaults from the glTF spec, used for primitives without a material.
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: Vec4::ONE,
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: Vec3::ZERO,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        Self {
+            name: material.name().map(str::to_string),
+            base_color_factor: Vec4::from_array(pbr.base_color_factor()),
+            base_color_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| info.texture().index()),
+            normal_texture: material.normal_texture().map(|normal| normal.texture().index()),
+            normal_scale: material.normal_texture().map_or(1.0, |normal| normal.scale()),
+            occlusion_texture: material.occlusion_texture().map(|occlusion| occlusion.texture().index()),
+            occlusion_strength: material.occlusion_texture().map_or(1.0, |occlusion| occlusion.strength()),
+            emissive_factor: Vec3::from_array(material.emissive_factor()),
+            emissive_texture: material.emissive_texture().map(|info| info.texture().index()),
+        }
+    }
+}
+
+// The textures a material points at, resolved against the scene's texture list.
+#[derive(Clone, Copy, Default)]
+pub struct MaterialTextures<'a> {
+    pub base_color: Option<&'a Texture>,
+    pub metallic_roughness: Option<&'a Texture>,
+    pub normal: Option<&'a Texture>,
+    pub occlusion: Option<&'a Texture>,
+    pub emissive: Option<&'a Texture>,
+}
+
+impl<'a> MaterialTextures<'a> {
+    pub fn resolve(material: &Material, textures: &'a [Texture]) -> Self {
+        let get = |index: Option<usize>| index.and_then(|i| textures.get(i));
+        Self {
+            base_color: get(material.base_color_texture),
+            metallic_roughness: get(material.metallic_roughness_texture),
+            normal: get(material.normal_texture),
+            occlusion: get(material.occlusion_texture),
+            emissive: get(material.emissive_texture),
+        }
+    }
+
+    // Fills the maps this material doesn't have from `fallback`.
+    pub fn or(self, fallback: &MaterialTextures<'a>) -> Self {
+        Self {
+            base_color: self.base_color.or(fallback.base_color),
+            metallic_roughness: self.metallic_roughness.or(fallback.metallic_roughness),
+            normal: self.normal.or(fallback.normal),
+            occlusion: self.occlusion.or(fallback.occlusion),
+            emissive: self.emissive.or(fallback.emissive),
+        }
+    }
+}
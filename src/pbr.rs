@@ -0,0 +1,147 @@
+use glam::{Vec2, Vec3};
+use std::f32::consts::PI;
+
+// Dielectrics reflect about 4% head on, whatever their color.
+pub const DIELECTRIC_F0: f32 = 0.04;
+
+// GGX / Trowbridge-Reitz normal distribution, alpha = roughness^2 like glTF.
+pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * d * d).max(1e-7)
+}
+
+// Smith shadowing-masking with the Schlick-GGX approximation for direct lights.
+pub fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+    g1(n_dot_v) * g1(n_dot_l)
+}
+
+pub fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Cook-Torrance GGX reflection of one light off a metallic-roughness surface, times n dot l.
+/// Vectors are normalized and point away from the surface, `base_color` is linear.
+///
+/// ```
+/// use glam::Vec3;
+/// use ruster::pbr::cook_torrance;
+///
+/// // a rough white dielectric lit head on reflects close to the lambertian 1 / pi
+/// let lit = cook_torrance(Vec3::Z, Vec3::Z, Vec3::Z, Vec3::ONE, 0.0, 1.0);
+/// assert!((lit.x - 1.0 / std::f32::consts::PI).abs() < 0.05);
+/// // light from behind contributes nothing
+/// assert_eq!(cook_torrance(Vec3::Z, -Vec3::Z, Vec3::Z, Vec3::ONE, 0.0, 1.0), Vec3::ZERO);
+/// ```
+pub fn cook_torrance(normal: Vec3, to_light: Vec3, to_eye: Vec3, base_color: Vec3, metallic: f32, roughness: f32) -> Vec3 {
+    let n_dot_l = normal.dot(to_light);
+    if n_dot_l <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let n_dot_v = normal.dot(to_eye).max(1e-4);
+    let half = (to_light + to_eye).normalize_or_zero();
+
+    let f0 = Vec3::splat(DIELECTRIC_F0).lerp(base_color, metallic);
+    let fresnel = fresnel_schlick(half.dot(to_eye).max(0.0), f0);
+    let specular = fresnel * distribution_ggx(normal.dot(half).max(0.0), roughness) * geometry_smith(n_dot_v, n_dot_l, roughness)
+        / (4.0 * n_dot_v * n_dot_l).max(1e-4);
+
+    // what isn't reflected is diffused, metals absorb it
+    let diffuse = (Vec3::ONE - fresnel) * (1.0 - metallic) * base_color / PI;
+    (diffuse + specular) * n_dot_l
+}
+
+// Schlick fresnel for ambient light, rough surfaces lose the bright rim at grazing angles.
+pub fn fresnel_schlick_roughness(cos_theta: f32, f0: Vec3, roughness: f32) -> Vec3 {
+    let max_reflectance = Vec3::splat(1.0 - roughness).max(f0);
+    f0 + (max_reflectance - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+// i-th of n points of the Hammersley set, evenly spread over the unit square.
+pub fn hammersley(i: u32, n: u32) -> Vec2 {
+    Vec2::new(i as f32 / n as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+// Half vector around `normal` distributed like the GGX lobe, for importance sampling.
+pub fn importance_sample_ggx(xi: Vec2, normal: Vec3, roughness: f32) -> Vec3 {
+    let alpha = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let local = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+    let up = if normal.z.abs() < 0.999 { Vec3::Z } else { Vec3::X };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * local.x + bitangent * local.y + normal * local.z).normalize()
+}
+
+// Split sum: scale and bias applied to f0 for the specular part of ambient light,
+// integrated over the GGX lobe. Image based lighting uses k = roughness^2 / 2 in the geometry term.
+pub fn integrate_brdf(n_dot_v: f32, roughness: f32, samples: u32) -> Vec2 {
+    let n_dot_v = n_dot_v.max(1e-4);
+    let view = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+    let k = roughness * roughness / 2.0;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+
+    let mut scale_bias = Vec2::ZERO;
+    for i in 0..samples {
+        let half = importance_sample_ggx(hammersley(i, samples), Vec3::Z, roughness);
+        let light = half * 2.0 * view.dot(half) - view;
+        let (n_dot_l, n_dot_h, v_dot_h) = (light.z, half.z.max(0.0), view.dot(half).max(0.0));
+        if n_dot_l > 0.0 {
+            let visibility = g1(n_dot_v) * g1(n_dot_l) * v_dot_h / (n_dot_h * n_dot_v).max(1e-4);
+            let fresnel = (1.0 - v_dot_h).powi(5);
+            scale_bias += Vec2::new((1.0 - fresnel) * visibility, fresnel * visibility);
+        }
+    }
+    scale_bias / samples as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ggx_distribution_integrates_to_one() {
+        // projected onto the surface, the microfacet normals cover it exactly once
+        let steps = 4096;
+        for roughness in [0.3, 0.6, 1.0] {
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let theta = (i as f32 + 0.5) / steps as f32 * PI / 2.0;
+                    distribution_ggx(theta.cos(), roughness) * theta.cos() * theta.sin() * 2.0 * PI * (PI / 2.0 / steps as f32)
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 0.01, "roughness {}: {}", roughness, integral);
+        }
+    }
+
+    #[test]
+    fn fresnel_goes_from_f0_to_white_at_grazing_angles() {
+        let f0 = Vec3::new(0.9, 0.5, 0.1);
+        assert_eq!(fresnel_schlick(1.0, f0), f0);
+        assert_eq!(fresnel_schlick(0.0, f0), Vec3::ONE);
+    }
+
+    #[test]
+    fn metals_have_no_diffuse_and_tint_their_highlights() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let to_light = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let to_eye = Vec3::new(-1.0, 0.0, 1.0).normalize();
+
+        // mirror direction: the red metal reflects red (bar a little fresnel white),
+        // the dielectric a white highlight over red diffuse
+        let metal = cook_torrance(Vec3::Z, to_light, to_eye, red, 1.0, 0.3);
+        assert!(metal.y == metal.z && metal.y < 0.01 * metal.x, "{}", metal);
+        let plastic = cook_torrance(Vec3::Z, to_light, to_eye, red, 0.0, 0.3);
+        assert!(plastic.x > plastic.y && plastic.y > 0.0 && plastic.y == plastic.z, "{}", plastic);
+
+        // away from the highlight a metal is close to black
+        let off_highlight = cook_torrance(Vec3::Z, to_light, Vec3::Z, red, 1.0, 0.3);
+        assert!(off_highlight.x < 0.1 * metal.x, "{} {}", off_highlight, metal);
+    }
+}
@@ -0,0 +1,96 @@
+// How a texture is filtered when looked up, lives on the Texture itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Filter {
+    // closest texel of the full resolution image
+    Nearest,
+    // 4 texels of the full resolution image, smooth when magnified
+    Bilinear,
+    // bilinear on the two mip levels around the pixel footprint, blended
+    #[default]
+    Trilinear,
+    // several trilinear taps spread along the long side of a stretched footprint,
+    // keeps floors and grazing surfaces sharp where trilinear would blur them
+    Anisotropic,
+}
+
+// What happens to texel coordinates outside the image, per axis like glTF samplers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+}
+
+impl Wrap {
+    /// Maps any integer texel coordinate into `0..size`.
+    ///
+    /// ```
+    /// use ruster::Wrap;
+    ///
+    /// assert_eq!(Wrap::Repeat.apply(-1, 4), 3);
+    /// assert_eq!(Wrap::ClampToEdge.apply(-1, 4), 0);
+    /// assert_eq!(Wrap::MirroredRepeat.apply(-1, 4), 0);
+    /// assert_eq!(Wrap::MirroredRepeat.apply(5, 4), 2);
+    /// ```
+    pub fn apply(self, x: i32, size: usize) -> usize {
+        let size = size as i32;
+        let x = match self {
+            Wrap::Repeat => x.rem_euclid(size),
+            Wrap::ClampToEdge => x.clamp(0, size - 1),
+            Wrap::MirroredRepeat => {
+                let m = x.rem_euclid(2 * size);
+                if m < size { m } else { 2 * size - 1 - m }
+            }
+        };
+        x as usize
+    }
+}
+
+impl From<gltf::texture::WrappingMode> for Wrap {
+    fn from(mode: gltf::texture::WrappingMode) -> Self {
+        match mode {
+            gltf::texture::WrappingMode::Repeat => Wrap::Repeat,
+            gltf::texture::WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+            gltf::texture::WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    // upper bound on taps per lookup for Filter::Anisotropic
+    pub max_anisotropy: u32,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            filter: Filter::default(),
+            wrap_u: Wrap::default(),
+            wrap_v: Wrap::default(),
+            max_anisotropy: 8,
+        }
+    }
+}
+
+impl Sampler {
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            ..Default::default()
+        }
+    }
+
+    // Wrap modes from the glTF sampler, filtering stays ours to pick.
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        Self {
+            wrap_u: sampler.wrap_s().into(),
+            wrap_v: sampler.wrap_t().into(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,533 @@
+use crate::camera::{RendererCamera, View};
+use crate::error::{Error, Result};
+use crate::framebuffer::Framebuffer;
+use crate::geometry::*;
+use crate::light::Lighting;
+use crate::material::{Material, MaterialTextures};
+use crate::sampler::Sampler;
+use crate::shader::{BlinnPhongShader, FragmentShader, LitVertexShader, PbrShader, TextureShader, TransformShader, VertexShader};
+use crate::shadow::{ShadowMap, ShadowMaps};
+use crate::texture::Texture;
+use crate::transform::Transform;
+use glam::{Mat4, Quat, Vec3};
+use std::path::Path;
+
+// Light every lit surface gets regardless of the lights, so unlit sides aren't pitch black.
+pub const AMBIENT: f32 = 0.1;
+
+// How render_scene shades the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shading {
+    // base color texture or vertex color times the base color factor, lights ignored
+    Unlit,
+    BlinnPhong,
+    // glTF metallic-roughness with all material maps
+    #[default]
+    Pbr,
+    // triangle outlines colored by tile
+    Wireframe,
+}
+
+// One thing to draw: a mesh placed in the world with the material it was authored with.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    // shown in the viewer's outliner, not required to be unique
+    pub name: String,
+    pub mesh: usize,
+    pub material: Option<usize>,
+    // the glTF node's own transform, what the outliner edits
+    pub transform: Transform,
+    // world matrix of the nodes above it, composed on load. Kept as a matrix: a non uniform scale
+    // above a rotated node shears it, which no Transform can hold
+    pub parent: Mat4,
+}
+
+impl Instance {
+    pub fn new(name: impl Into<String>, mesh: usize, material: Option<usize>, transform: Transform) -> Self {
+        Self {
+            name: name.into(),
+            mesh,
+            material,
+            transform,
+            parent: Mat4::IDENTITY,
+        }
+    }
+
+    pub fn with_parent(self, parent: Mat4) -> Self {
+        Self { parent, ..self }
+    }
+
+    pub fn model(&self) -> Mat4 {
+        self.parent * self.transform.local()
+    }
+}
+
+// Meshes are stored once and referenced by index, so nodes instancing
+// the same glTF mesh (the four wheels of a car) share the vertex data.
+#[derive(Default)]
+pub struct Scene {
+    pub meshes: Vec<MeshRenderer>,
+    pub materials: Vec<Material>,
+    // indexed like the glTF textures, so Material texture indices can be used directly
+    pub textures: Vec<Texture>,
+    pub instances: Vec<Instance>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_gltf(path: &Path) -> Result<Self> {
+        let (document, buffers, images) = gltf::import(path)?;
+        if document.meshes().next().is_none() {
+            return Err(Error::NoMeshes);
+        }
+
+        let mut scene = Scene::new();
+        scene.materials = document.materials().map(|material| Material::from_gltf(&material)).collect();
+        scene.textures = document
+            .textures()
+            .map(|texture| {
+                let mut result = Texture::from_gltf_image(&images[texture.source().index()]);
+                result.sampler = Sampler::from_gltf(&texture.sampler());
+                result
+            })
+            .collect();
+
+        // every glTF primitive becomes its own MeshRenderer since each may use a different material
+        let mut primitives_of_mesh: Vec<Vec<(usize, Option<usize>)>> = Vec::new();
+        for mesh in document.meshes() {
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                scene.meshes.push(MeshRenderer::load_primitive_from_gltf(&primitive, &buffers)?);
+                primitives.push((scene.meshes.len() - 1, primitive.material().index()));
+            }
+            primitives_of_mesh.push(primitives);
+        }
+
+        // glTF files without a scene still list their root nodes in the first one, if any
+        if let Some(gltf_scene) = document.default_scene().or_else(|| document.scenes().next()) {
+            for node in gltf_scene.nodes() {
+                scene.add_node(&node, Mat4::IDENTITY, &primitives_of_mesh);
+            }
+        }
+
+        Ok(scene)
+    }
+
+    // Walks the node tree depth first, composing local matrices down to world space.
+    fn add_node(&mut self, node: &gltf::Node, parent: Mat4, primitives_of_mesh: &[Vec<(usize, Option<usize>)>]) {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let local = Transform::new(
+            Vec3::from_array(translation),
+            Quat::from_array(rotation),
+            Vec3::from_array(scale),
+        );
+        let world = parent * local.local();
+
+        if let Some(mesh) = node.mesh() {
+            let name = node
+                .name()
+                .or(mesh.name())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Node {}", node.index()));
+            let primitives = &primitives_of_mesh[mesh.index()];
+            for (i, &(mesh_id, material)) in primitives.iter().enumerate() {
+                let name = if primitives.len() > 1 { format!("{} ({})", name, i) } else { name.clone() };
+                self.instances.push(Instance::new(name, mesh_id, material, local).with_parent(parent));
+            }
+        }
+
+        for child in node.children() {
+            self.add_node(&child, world, primitives_of_mesh);
+        }
+    }
+
+    pub fn material(&self, instance: &Instance) -> Option<&Material> {
+        instance.material.and_then(|id| self.materials.get(id))
+    }
+
+    // World space box around every instance, None for an empty scene.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut min = Vec3::INFINITY;
+        let mut max = Vec3::NEG_INFINITY;
+        for instance in self.instances.iter() {
+            let model = instance.model();
+            for vertex in self.meshes[instance.mesh].vertices() {
+                let position = model.transform_point3(vertex.position.truncate());
+                min = min.min(position);
+                max = max.max(position);
+            }
+        }
+        (min.x <= max.x).then_some((min, max))
+    }
+
+    pub fn material_textures(&self, instance: &Instance) -> MaterialTextures<'_> {
+        self.material(instance)
+            .map(|material| MaterialTextures::resolve(material, &self.textures))
+            .unwrap_or_default()
+    }
+}
+
+// What a SceneShader knows about the instance it picks shaders for.
+pub struct ShadingContext<'a> {
+    pub model: Mat4,
+    pub view_projection: Mat4,
+    pub camera_position: Vec3,
+    pub material: &'a Material,
+    // the material's maps, with the fallback ones filled in
+    pub textures: MaterialTextures<'a>,
+    pub lighting: &'a Lighting<'a>,
+    pub shadows: &'a [Option<ShadowMap>],
+}
+
+/// Picks the vertex and fragment shader every instance is drawn with, so render_scene_with
+/// draws scenes with shaders written outside the crate. Shading's built in modes are SceneShaders too.
+///
+/// ```
+/// use ruster::scene::{SceneShader, ShadingContext};
+/// use ruster::shader::{NormalShader, TransformShader};
+///
+/// // object space normals as colors
+/// struct Normals;
+/// impl SceneShader for Normals {
+///     type Vertex<'a> = TransformShader;
+///     type Fragment<'a> = NormalShader;
+///     fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (TransformShader, NormalShader) {
+///         (TransformShader { mvp: context.view_projection * context.model }, NormalShader)
+///     }
+/// }
+/// ```
+pub trait SceneShader: Sync {
+    type Vertex<'a>: VertexShader
+    where
+        Self: 'a;
+    type Fragment<'a>: FragmentShader<<Self::Vertex<'a> as VertexShader>::Varyings>
+    where
+        Self: 'a;
+
+    // shadow maps are only rendered for shaders that read them
+    fn uses_shadows(&self) -> bool {
+        false
+    }
+
+    fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (Self::Vertex<'a>, Self::Fragment<'a>);
+}
+
+// Base color texture or vertex color times the base color factor, lights ignored.
+pub struct UnlitShading;
+
+impl SceneShader for UnlitShading {
+    type Vertex<'a> = TransformShader;
+    type Fragment<'a> = TextureShader<'a>;
+
+    fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (TransformShader, TextureShader<'a>) {
+        (
+            TransformShader { mvp: context.view_projection * context.model },
+            TextureShader {
+                texture: context.textures.base_color,
+                base_color: context.material.base_color_factor,
+            },
+        )
+    }
+}
+
+pub struct BlinnPhongShading;
+
+impl SceneShader for BlinnPhongShading {
+    type Vertex<'a> = LitVertexShader;
+    type Fragment<'a> = BlinnPhongShader<'a>;
+
+    fn uses_shadows(&self) -> bool {
+        true
+    }
+
+    fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (LitVertexShader, BlinnPhongShader<'a>) {
+        let material = context.material;
+        // rough surfaces get wide dim highlights: the usual roughness to Blinn-Phong exponent
+        // mapping, and a specular that fades out on fully rough materials
+        let roughness = material.roughness_factor.clamp(0.05, 1.0);
+        (
+            LitVertexShader::new(context.model, context.view_projection),
+            BlinnPhongShader {
+                texture: context.textures.base_color,
+                normal_map: context.textures.normal,
+                normal_scale: material.normal_scale,
+                base_color: material.base_color_factor,
+                lights: context.lighting.lights,
+                shadows: context.shadows,
+                camera_position: context.camera_position,
+                ambient: Vec3::splat(AMBIENT),
+                specular: 0.5 * (1.0 - roughness),
+                shininess: (2.0 / roughness.powi(4) - 2.0).max(1.0),
+            },
+        )
+    }
+}
+
+pub struct PbrShading;
+
+impl SceneShader for PbrShading {
+    type Vertex<'a> = LitVertexShader;
+    type Fragment<'a> = PbrShader<'a>;
+
+    fn uses_shadows(&self) -> bool {
+        true
+    }
+
+    fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (LitVertexShader, PbrShader<'a>) {
+        (
+            LitVertexShader::new(context.model, context.view_projection),
+            PbrShader {
+                material: context.material,
+                textures: context.textures,
+                lights: context.lighting.lights,
+                shadows: context.shadows,
+                environment: context.lighting.environment,
+                camera_position: context.camera_position,
+                ambient: Vec3::splat(AMBIENT),
+            },
+        )
+    }
+}
+
+// Draws every instance of the scene into the framebuffer with one of the built in shadings.
+pub fn render_scene(
+    scene: &Scene,
+    camera: &RendererCamera,
+    lighting: &Lighting,
+    fallback: &MaterialTextures,
+    framebuffer: &Framebuffer,
+    shading: Shading,
+) {
+    let view = View::from(camera);
+    match shading {
+        Shading::Unlit => render_scene_with(scene, &view, lighting, fallback, framebuffer, &UnlitShading, false),
+        Shading::BlinnPhong => render_scene_with(scene, &view, lighting, fallback, framebuffer, &BlinnPhongShading, false),
+        Shading::Pbr => render_scene_with(scene, &view, lighting, fallback, framebuffer, &PbrShading, false),
+        Shading::Wireframe => render_scene_with(scene, &view, lighting, fallback, framebuffer, &UnlitShading, true),
+    }
+}
+
+// Draws every instance of the scene as seen from `view` into the framebuffer with the shaders
+// `shader` picks, binned together in one pass so they share its depth buffer. Any target works,
+// shadow maps are rendered by this too. `fallback` fills in the maps
+// an instance's material doesn't have. An environment in `lighting` also fills the pixels
+// nothing covered, except in wireframe.
+pub fn render_scene_with<S: SceneShader>(
+    scene: &Scene,
+    view: &View,
+    lighting: &Lighting,
+    fallback: &MaterialTextures,
+    framebuffer: &Framebuffer,
+    shader: &S,
+    wireframe: bool,
+) {
+    let view_projection = view.view_projection;
+    let default_material = Material::default();
+
+    // one depth pass per shadow casting light, over the same instances
+    let frame_shadows;
+    let shadow_maps = match lighting.shadows {
+        Some(shadow_maps) => shadow_maps,
+        None => {
+            frame_shadows = ShadowMaps::default();
+            &frame_shadows
+        }
+    };
+    let shadows = scene.bounds().filter(|_| shader.uses_shadows()).map(|(min, max)| {
+        let (center, radius) = ((min + max) * 0.5, ((max - min).length() * 0.5).max(1e-3));
+        shadow_maps.update(scene, lighting.lights, center, radius)
+    });
+    let shadows = shadows.as_deref().map_or(&[][..], |maps| &maps[..]);
+
+    let draws: Vec<_> = scene
+        .instances
+        .iter()
+        .map(|instance| {
+            let context = ShadingContext {
+                model: instance.model(),
+                view_projection,
+                camera_position: view.position,
+                material: scene.material(instance).unwrap_or(&default_material),
+                textures: scene.material_textures(instance).or(fallback),
+                lighting,
+                shadows,
+            };
+            let (vertex_shader, fragment_shader) = shader.shaders(&context);
+            DrawCall {
+                mesh: &scene.meshes[instance.mesh],
+                vertex_shader,
+                fragment_shader,
+            }
+        })
+        .collect();
+    render_draws(&draws, framebuffer, wireframe);
+
+    if let Some(environment) = lighting.environment.filter(|_| !wireframe) {
+        environment.draw_background(framebuffer, &view_projection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{TempDir, material_triangle_gltf, png, triangle_gltf};
+    use crate::material::MaterialTextures;
+    use crate::sampler::Wrap;
+    use crate::shader::Fragment;
+    use crate::utilities::to_argb;
+    use glam::Vec4;
+
+    #[test]
+    fn node_transforms_compose_down_the_tree() {
+        let dir = TempDir::new("scene-nodes");
+        let nodes = r#"[
+            { "translation": [1, 0, 0], "scale": [2, 2, 2], "children": [1, 2] },
+            { "translation": [0, 1, 0], "mesh": 0 },
+            { "children": [3] },
+            { "rotation": [0, 0, 1, 0], "mesh": 0 }
+        ]"#;
+        let scene = Scene::load_gltf(&triangle_gltf(&dir, nodes)).unwrap();
+
+        // the mesh is loaded once and drawn by both nodes using it
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.instances.len(), 2);
+        assert!(scene.instances.iter().all(|instance| instance.mesh == 0 && instance.material.is_none()));
+        // neither the nodes nor the mesh are named
+        assert_eq!(scene.instances[0].name, "Node 1");
+        assert_eq!(scene.instances[1].name, "Node 3");
+
+        let parent = Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::IDENTITY, Vec3::X);
+        let first = parent * Mat4::from_translation(Vec3::Y);
+        let second = parent * Mat4::from_quat(Quat::from_rotation_z(std::f32::consts::PI));
+        assert!(scene.instances[0].model().abs_diff_eq(first, 1e-6), "{}", scene.instances[0].model());
+        assert!(scene.instances[1].model().abs_diff_eq(second, 1e-6), "{}", scene.instances[1].model());
+    }
+
+    #[test]
+    fn non_uniform_parent_scale_shears_rotated_children() {
+        let dir = TempDir::new("scene-shear");
+        let (sin, cos) = std::f32::consts::FRAC_PI_8.sin_cos();
+        let nodes = format!(
+            r#"[{{ "scale": [2, 1, 1], "children": [1] }}, {{ "rotation": [0, 0, {}, {}], "mesh": 0 }}]"#,
+            sin, cos
+        );
+        let scene = Scene::load_gltf(&triangle_gltf(&dir, &nodes)).unwrap();
+
+        let world = Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0)) * Mat4::from_quat(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4));
+        let model = scene.instances[0].model();
+        assert!(model.abs_diff_eq(world, 1e-6), "{model} != {world}");
+        // the child's x and y axes aren't perpendicular anymore
+        assert!(model.transform_vector3(Vec3::X).dot(model.transform_vector3(Vec3::Y)).abs() > 0.5);
+        // and the outliner still edits the node's own rotation
+        assert!(scene.instances[0].transform.rotation.abs_diff_eq(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4), 1e-6));
+    }
+
+    #[test]
+    fn materials_and_their_textures_are_loaded() {
+        let dir = TempDir::new("scene-materials");
+        // red then half transparent green
+        dir.write("albedo.png", png(2, 1, png::ColorType::Rgba, png::BitDepth::Eight, &[255, 0, 0, 255, 0, 255, 0, 128]));
+        let assets = r#"
+            "materials": [{
+                "name": "paint",
+                "pbrMetallicRoughness": { "baseColorFactor": [0.5, 1, 1, 1], "baseColorTexture": { "index": 0 }, "metallicFactor": 0.25 },
+                "emissiveFactor": [0, 0, 1]
+            }],
+            "textures": [{ "source": 0 }],
+            "images": [{ "uri": "albedo.png" }]"#;
+        let scene = Scene::load_gltf(&material_triangle_gltf(&dir, r#"[{ "mesh": 0 }]"#, assets)).unwrap();
+
+        let instance = &scene.instances[0];
+        assert_eq!(instance.material, Some(0));
+        let material = scene.material(instance).unwrap();
+        assert_eq!(material.name.as_deref(), Some("paint"));
+        assert_eq!(material.base_color_factor, Vec4::new(0.5, 1.0, 1.0, 1.0));
+        assert_eq!(material.metallic_factor, 0.25);
+        // unset factors keep the spec defaults
+        assert_eq!(material.roughness_factor, 1.0);
+        assert_eq!(material.emissive_factor, Vec3::Z);
+        assert!(material.normal_texture.is_none());
+
+        let textures = scene.material_textures(instance);
+        let texture = textures.base_color.unwrap();
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(texture.data, [to_argb(255, 255, 0, 0), to_argb(128, 0, 255, 0)]);
+
+        // fallback maps only fill in what the material doesn't have
+        let fallback_texture = Texture::from_argb(1, 1, vec![0]);
+        let fallback = MaterialTextures { base_color: Some(&fallback_texture), normal: Some(&fallback_texture), ..Default::default() };
+        let textures = textures.or(&fallback);
+        assert!(std::ptr::eq(textures.base_color.unwrap(), texture));
+        assert!(std::ptr::eq(textures.normal.unwrap(), &fallback_texture));
+        assert!(textures.occlusion.is_none());
+    }
+
+    #[test]
+    fn texture_samplers_set_the_wrap_modes() {
+        let dir = TempDir::new("scene-samplers");
+        dir.write("albedo.png", png(1, 1, png::ColorType::Grayscale, png::BitDepth::Eight, &[0]));
+        let assets = r#"
+            "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 1 } } }],
+            "samplers": [{ "wrapS": 33071, "wrapT": 33648 }],
+            "textures": [{ "source": 0 }, { "source": 0, "sampler": 0 }],
+            "images": [{ "uri": "albedo.png" }]"#;
+        let scene = Scene::load_gltf(&material_triangle_gltf(&dir, r#"[{ "mesh": 0 }]"#, assets)).unwrap();
+
+        // textures without a sampler repeat
+        assert_eq!((scene.textures[0].sampler.wrap_u, scene.textures[0].sampler.wrap_v), (Wrap::Repeat, Wrap::Repeat));
+        let sampler = scene.textures[1].sampler;
+        assert_eq!((sampler.wrap_u, sampler.wrap_v), (Wrap::ClampToEdge, Wrap::MirroredRepeat));
+    }
+
+    // Flat color from outside the built in shaders, the way a user crate would add one.
+    struct Flat(Vec4);
+
+    impl FragmentShader<Vertex> for Flat {
+        fn shade(&self, _fragment: &Fragment<Vertex>) -> Option<Vec4> {
+            Some(self.0)
+        }
+    }
+
+    impl SceneShader for Flat {
+        type Vertex<'a> = TransformShader;
+        type Fragment<'a> = &'a Flat;
+
+        fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (TransformShader, &'a Flat) {
+            (TransformShader { mvp: context.view_projection * context.model }, self)
+        }
+    }
+
+    #[test]
+    fn render_scene_with_draws_custom_shaders() {
+        let dir = TempDir::new("scene-custom");
+        let scene = Scene::load_gltf(&triangle_gltf(&dir, r#"[{ "mesh": 0 }]"#)).unwrap();
+        let framebuffer = Framebuffer::new(64, 64);
+        framebuffer.clear(0);
+        let shader = Flat(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        render_scene_with(&scene, &View::from(&RendererCamera::default()), &Lighting::default(), &MaterialTextures::default(), &framebuffer, &shader, false);
+
+        let red = to_argb(255, 255, 0, 0);
+        let pixels: Vec<u32> = framebuffer.buffer.iter().map(|p| p.load(std::sync::atomic::Ordering::Relaxed)).collect();
+        assert!(pixels.contains(&red));
+        assert!(pixels.iter().all(|&p| p == red || p == 0));
+    }
+
+    #[test]
+    fn unlit_shading_tints_with_the_base_color_factor() {
+        let dir = TempDir::new("scene-tint");
+        let assets = r#""materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0.5, 0, 1] } }]"#;
+        let scene = Scene::load_gltf(&material_triangle_gltf(&dir, r#"[{ "mesh": 0 }]"#, assets)).unwrap();
+        let framebuffer = Framebuffer::new(64, 64);
+        framebuffer.clear(0);
+        render_scene(&scene, &RendererCamera::default(), &Lighting::default(), &MaterialTextures::default(), &framebuffer, Shading::Unlit);
+
+        // white vertex colors, no texture: the factor is the whole color
+        let orange = to_argb(255, 255, 128, 0);
+        let pixels: Vec<u32> = framebuffer.buffer.iter().map(|p| p.load(std::sync::atomic::Ordering::Relaxed)).collect();
+        assert!(pixels.contains(&orange));
+        assert!(pixels.iter().all(|&p| p == orange || p == 0));
+    }
+}
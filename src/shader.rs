@@ -0,0 +1,452 @@
+use crate::environment::Environment;
+use crate::geometry::Vertex;
+use crate::light::{Light, blinn_phong};
+use crate::material::{Material, MaterialTextures};
+use crate::pbr::{DIELECTRIC_F0, cook_torrance, fresnel_schlick_roughness};
+use crate::shadow::ShadowMap;
+use crate::texture::{Texture, linear_to_srgb, srgb_to_linear};
+use crate::utilities::*;
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use std::ops::{Add, Mul, Sub};
+
+// Anything a vertex shader hands to the fragment shader. It only has to support
+// the arithmetic used to interpolate it, glam vectors and Vertex already do.
+// Use impl_varyings! to get the operators for a struct of such fields.
+pub trait Varyings: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> + Send + Sync {}
+
+impl<T> Varyings for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> + Send + Sync {}
+
+// Implements Add, Sub and Mul<f32> field by field for a varyings struct.
+#[macro_export]
+macro_rules! impl_varyings {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl std::ops::Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                Self { $($field: self.$field + rhs.$field),* }
+            }
+        }
+
+        impl std::ops::Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                Self { $($field: self.$field - rhs.$field),* }
+            }
+        }
+
+        impl std::ops::Mul<f32> for $name {
+            type Output = Self;
+            fn mul(self, rhs: f32) -> Self {
+                Self { $($field: self.$field * rhs),* }
+            }
+        }
+    };
+}
+
+// Vertex shader result: clip space position plus whatever gets interpolated.
+#[derive(Debug, Copy, Clone)]
+pub struct VertexOutput<V> {
+    pub position: Vec4,
+    pub varyings: V,
+}
+
+impl<V: Varyings> Add for VertexOutput<V> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            position: self.position + rhs.position,
+            varyings: self.varyings + rhs.varyings,
+        }
+    }
+}
+
+impl<V: Varyings> Sub for VertexOutput<V> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            position: self.position - rhs.position,
+            varyings: self.varyings - rhs.varyings,
+        }
+    }
+}
+
+impl<V: Varyings> Mul<f32> for VertexOutput<V> {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            position: self.position * rhs,
+            varyings: self.varyings * rhs,
+        }
+    }
+}
+
+pub trait VertexShader: Sync {
+    type Varyings: Varyings;
+
+    fn shade(&self, vertex: &Vertex) -> VertexOutput<Self::Varyings>;
+}
+
+/// Returns the pixel color as rgba in [0, 1], or None to discard the fragment.
+///
+/// ```
+/// use glam::{Vec3, Vec4};
+/// use ruster::shader::{Fragment, FragmentShader, VertexOutput, VertexShader};
+/// use ruster::{Vertex, impl_varyings};
+///
+/// #[derive(Clone, Copy)]
+/// struct Tint { color: Vec3 }
+/// impl_varyings!(Tint { color });
+///
+/// struct Flat;
+/// impl VertexShader for Flat {
+///     type Varyings = Tint;
+///     fn shade(&self, vertex: &Vertex) -> VertexOutput<Tint> {
+///         VertexOutput { position: vertex.position, varyings: Tint { color: vertex.color } }
+///     }
+/// }
+/// impl FragmentShader<Tint> for Flat {
+///     fn shade(&self, fragment: &Fragment<Tint>) -> Option<Vec4> {
+///         Some(fragment.varyings.color.extend(1.0))
+///     }
+/// }
+/// ```
+pub trait FragmentShader<V>: Sync {
+    fn shade(&self, fragment: &Fragment<V>) -> Option<Vec4>;
+}
+
+// Borrowed shaders are shaders too, so a DrawCall can hold either.
+impl<T: VertexShader + ?Sized> VertexShader for &T {
+    type Varyings = T::Varyings;
+
+    fn shade(&self, vertex: &Vertex) -> VertexOutput<Self::Varyings> {
+        (**self).shade(vertex)
+    }
+}
+
+impl<V, T: FragmentShader<V> + ?Sized> FragmentShader<V> for &T {
+    fn shade(&self, fragment: &Fragment<V>) -> Option<Vec4> {
+        (**self).shade(fragment)
+    }
+}
+
+// Per triangle setup to interpolate varyings perspective correctly anywhere on screen.
+pub struct Interpolator<V> {
+    pub screen: [Vec2; 3],
+    pub area: f32,
+    // varyings and 1 / w, already divided by w
+    pub varyings: [V; 3],
+    pub rec_w: [f32; 3],
+}
+
+impl<V: Varyings> Interpolator<V> {
+    pub fn at_barycentric(&self, bary: Vec3) -> V {
+        let correction = 1.0 / (bary.x * self.rec_w[0] + bary.y * self.rec_w[1] + bary.z * self.rec_w[2]);
+        (self.varyings[0] * bary.x + self.varyings[1] * bary.y + self.varyings[2] * bary.z) * correction
+    }
+
+    pub fn at(&self, point: Vec2) -> V {
+        let [sc0, sc1, sc2] = self.screen;
+        self.at_barycentric(barycentric_weights(point, sc0, sc1, sc2, self.area))
+    }
+}
+
+pub struct Fragment<'a, V> {
+    pub varyings: V,
+    // pixel center in screen space
+    pub position: Vec2,
+    // view depth (clip w), smaller is closer
+    pub depth: f32,
+    pub interpolator: &'a Interpolator<V>,
+}
+
+impl<V: Varyings> Fragment<'_, V> {
+    // How much the varyings change one pixel to the right, like a 2x2 quad would give.
+    // Computed on demand, only texture lookups need it.
+    pub fn ddx(&self) -> V {
+        self.interpolator.at(self.position + glam::vec2(1.0, 0.0)) - self.varyings
+    }
+
+    pub fn ddy(&self) -> V {
+        self.interpolator.at(self.position + glam::vec2(0.0, 1.0)) - self.varyings
+    }
+}
+
+// Vertex attributes carried through untouched, position moved to clip space.
+pub struct TransformShader {
+    pub mvp: Mat4,
+}
+
+impl VertexShader for TransformShader {
+    type Varyings = Vertex;
+
+    fn shade(&self, vertex: &Vertex) -> VertexOutput<Vertex> {
+        VertexOutput {
+            position: self.mvp * vertex.position,
+            varyings: *vertex,
+        }
+    }
+}
+
+// Vertex color, or the texture when there is one, times `base_color`.
+pub struct TextureShader<'a> {
+    pub texture: Option<&'a Texture>,
+    pub base_color: Vec4,
+}
+
+impl FragmentShader<Vertex> for TextureShader<'_> {
+    fn shade(&self, fragment: &Fragment<Vertex>) -> Option<Vec4> {
+        let color = match self.texture {
+            Some(texture) => texture.sample_rgba(fragment.varyings.uv, fragment.ddx().uv, fragment.ddy().uv),
+            None => fragment.varyings.color.extend(1.0),
+        };
+        Some(color * self.base_color)
+    }
+}
+
+// Object space normal mapped from [-1, 1] to a color.
+pub struct NormalShader;
+
+impl FragmentShader<Vertex> for NormalShader {
+    fn shade(&self, fragment: &Fragment<Vertex>) -> Option<Vec4> {
+        let normal = fragment.varyings.normal.normalize_or_zero();
+        Some((normal * 0.5 + 0.5).extend(1.0))
+    }
+}
+
+// Grayscale view depth, white at `near` fading to black at `far`.
+pub struct DepthShader {
+    pub near: f32,
+    pub far: f32,
+}
+
+impl<V> FragmentShader<V> for DepthShader {
+    fn shade(&self, fragment: &Fragment<V>) -> Option<Vec4> {
+        let t = map_to_range(fragment.depth, self.near, self.far, 1.0, 0.0).clamp(0.0, 1.0);
+        Some(Vec4::new(t, t, t, 1.0))
+    }
+}
+
+// What the lit shaders interpolate, positions and normals in world space.
+#[derive(Debug, Copy, Clone)]
+pub struct LitVaryings {
+    pub world_position: Vec3,
+    pub normal: Vec3,
+    pub color: Vec3,
+    pub uv: Vec2,
+    pub tangent: Vec4,
+}
+
+crate::impl_varyings!(LitVaryings { world_position, normal, color, uv, tangent });
+
+// Tangent frame of the surface under a fragment. Meshes without tangents get one built from
+// screen space derivatives of position and uv, the same fallback reference viewers use.
+fn surface_tangent(fragment: &Fragment<LitVaryings>, ddx: &LitVaryings, ddy: &LitVaryings) -> Vec4 {
+    let tangent = fragment.varyings.tangent;
+    if tangent.truncate().length_squared() > 0.0 {
+        return tangent;
+    }
+
+    // flipped v like generate_tangents, screen y pointing down cancels out of the solve
+    let (duv_dx, duv_dy) = (ddx.uv * glam::vec2(1.0, -1.0), ddy.uv * glam::vec2(1.0, -1.0));
+    let det = duv_dx.x * duv_dy.y - duv_dy.x * duv_dx.y;
+    if det.abs() < 1e-12 {
+        return Vec4::ZERO;
+    }
+    ((ddx.world_position * duv_dy.y - ddy.world_position * duv_dx.y) / det).extend(1.0)
+}
+
+// How much of light `i` reaches the fragment, offset along the unperturbed surface normal.
+fn shadow_visibility(shadows: &[Option<ShadowMap>], i: usize, fragment: &Fragment<LitVaryings>) -> f32 {
+    match shadows.get(i) {
+        Some(Some(shadow)) => shadow.visibility(fragment.varyings.world_position, fragment.varyings.normal.normalize_or_zero()),
+        _ => 1.0,
+    }
+}
+
+// Bends `normal` by a tangent space normal map texel, green pointing along the bitangent.
+pub fn perturb_normal(normal: Vec3, tangent: Vec4, texel: Vec4, scale: f32) -> Vec3 {
+    let t = (tangent.truncate() - normal * normal.dot(tangent.truncate())).normalize_or_zero();
+    if t == Vec3::ZERO {
+        return normal;
+    }
+    let b = normal.cross(t) * tangent.w.signum();
+    let local = texel.truncate() * 2.0 - 1.0;
+    (t * local.x * scale + b * local.y * scale + normal * local.z).normalize_or(normal)
+}
+
+// Moves vertices to world space for lighting and to clip space for rasterizing.
+pub struct LitVertexShader {
+    pub model: Mat4,
+    pub view_projection: Mat4,
+    // inverse transpose of the model matrix, keeps normals perpendicular under non uniform scale
+    pub normal_matrix: Mat3,
+}
+
+impl LitVertexShader {
+    pub fn new(model: Mat4, view_projection: Mat4) -> Self {
+        Self {
+            model,
+            view_projection,
+            normal_matrix: Mat3::from_mat4(model).inverse().transpose(),
+        }
+    }
+}
+
+impl VertexShader for LitVertexShader {
+    type Varyings = LitVaryings;
+
+    fn shade(&self, vertex: &Vertex) -> VertexOutput<LitVaryings> {
+        let world = self.model * vertex.position;
+        VertexOutput {
+            position: self.view_projection * world,
+            varyings: LitVaryings {
+                world_position: world.truncate(),
+                normal: self.normal_matrix * vertex.normal,
+                color: vertex.color,
+                uv: vertex.uv,
+                // tangents follow the surface like positions do, not like normals
+                tangent: (Mat3::from_mat4(self.model) * vertex.tangent.truncate()).extend(vertex.tangent.w),
+            },
+        }
+    }
+}
+
+// Per pixel Blinn-Phong over a list of lights. Albedo is the texture (or vertex color) times `base_color`.
+pub struct BlinnPhongShader<'a> {
+    pub texture: Option<&'a Texture>,
+    pub normal_map: Option<&'a Texture>,
+    pub normal_scale: f32,
+    pub base_color: Vec4,
+    pub lights: &'a [Light],
+    pub shadows: &'a [Option<ShadowMap>],
+    pub camera_position: Vec3,
+    pub ambient: Vec3,
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+impl FragmentShader<LitVaryings> for BlinnPhongShader<'_> {
+    fn shade(&self, fragment: &Fragment<LitVaryings>) -> Option<Vec4> {
+        let varyings = &fragment.varyings;
+        let (ddx, ddy) = (fragment.ddx(), fragment.ddy());
+        let albedo = match self.texture {
+            Some(texture) => texture.sample_rgba(varyings.uv, ddx.uv, ddy.uv),
+            None => varyings.color.extend(1.0),
+        } * self.base_color;
+
+        let mut normal = varyings.normal.normalize_or_zero();
+        if let Some(normal_map) = self.normal_map {
+            let texel = normal_map.sample_rgba(varyings.uv, ddx.uv, ddy.uv);
+            normal = perturb_normal(normal, surface_tangent(fragment, &ddx, &ddy), texel, self.normal_scale);
+        }
+        let to_eye = (self.camera_position - varyings.world_position).normalize_or_zero();
+
+        let mut color = self.ambient * albedo.truncate();
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Some((to_light, radiance)) = light.incident(varyings.world_position) {
+                let radiance = radiance * shadow_visibility(self.shadows, i, fragment);
+                color += radiance * blinn_phong(normal, to_light, to_eye, albedo.truncate(), self.specular, self.shininess);
+            }
+        }
+        Some(color.extend(albedo.w))
+    }
+}
+
+// glTF metallic-roughness shading: Cook-Torrance GGX per light, plus ambient, occlusion and emission.
+// With an environment the ambient term is image based lighting instead of a flat `ambient`.
+// Lighting is done in linear space and written out sRGB encoded.
+pub struct PbrShader<'a> {
+    pub material: &'a Material,
+    pub textures: MaterialTextures<'a>,
+    pub lights: &'a [Light],
+    pub shadows: &'a [Option<ShadowMap>],
+    pub environment: Option<&'a Environment>,
+    pub camera_position: Vec3,
+    pub ambient: Vec3,
+}
+
+impl FragmentShader<LitVaryings> for PbrShader<'_> {
+    fn shade(&self, fragment: &Fragment<LitVaryings>) -> Option<Vec4> {
+        let varyings = &fragment.varyings;
+        let (ddx, ddy) = (fragment.ddx(), fragment.ddy());
+        let sample = |texture: Option<&Texture>| texture.map(|t| t.sample_rgba(varyings.uv, ddx.uv, ddy.uv));
+
+        let base_texel = sample(self.textures.base_color).unwrap_or(Vec4::ONE);
+        let base_color = self.material.base_color_factor
+            * srgb_to_linear(base_texel.truncate()).extend(base_texel.w)
+            * varyings.color.extend(1.0);
+
+        // roughness in green, metalness in blue
+        let metal_rough = sample(self.textures.metallic_roughness).unwrap_or(Vec4::ONE);
+        let roughness = (self.material.roughness_factor * metal_rough.y).clamp(0.04, 1.0);
+        let metallic = (self.material.metallic_factor * metal_rough.z).clamp(0.0, 1.0);
+
+        let occlusion = sample(self.textures.occlusion)
+            .map_or(1.0, |texel| 1.0 + self.material.occlusion_strength * (texel.x - 1.0));
+        let emissive = self.material.emissive_factor
+            * sample(self.textures.emissive).map_or(Vec3::ONE, |texel| srgb_to_linear(texel.truncate()));
+
+        let mut normal = varyings.normal.normalize_or_zero();
+        if let Some(texel) = sample(self.textures.normal) {
+            normal = perturb_normal(normal, surface_tangent(fragment, &ddx, &ddy), texel, self.material.normal_scale);
+        }
+        let to_eye = (self.camera_position - varyings.world_position).normalize_or_zero();
+
+        let albedo = base_color.truncate();
+        let ambient = match self.environment {
+            Some(environment) => {
+                // split sum: irradiance for diffuse, prefiltered radiance times the BRDF lookup for specular
+                let n_dot_v = normal.dot(to_eye).max(1e-4);
+                let f0 = Vec3::splat(DIELECTRIC_F0).lerp(albedo, metallic);
+                let fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+                let diffuse = (Vec3::ONE - fresnel) * (1.0 - metallic) * albedo * environment.irradiance(normal);
+                let brdf = environment.brdf(n_dot_v, roughness);
+                let reflection = normal * 2.0 * normal.dot(to_eye) - to_eye;
+                let specular = environment.specular(reflection, roughness) * (fresnel * brdf.x + brdf.y);
+                diffuse + specular
+            }
+            None => self.ambient * albedo,
+        };
+
+        let mut color = ambient * occlusion + emissive;
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Some((to_light, radiance)) = light.incident(varyings.world_position) {
+                let radiance = radiance * shadow_visibility(self.shadows, i, fragment);
+                color += radiance * cook_torrance(normal, to_light, to_eye, albedo, metallic, roughness);
+            }
+        }
+        Some(linear_to_srgb(color).extend(base_color.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_map_texels_follow_the_tangent_frame() {
+        let tangent = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        // the flat texel leaves the normal alone
+        assert!(perturb_normal(Vec3::Z, tangent, Vec4::new(0.5, 0.5, 1.0, 1.0), 1.0).abs_diff_eq(Vec3::Z, 1e-6));
+        // red leans along the tangent, green along the bitangent, whose side w picks
+        let red = Vec4::new(1.0, 0.5, 0.5, 1.0);
+        let green = Vec4::new(0.5, 1.0, 0.5, 1.0);
+        assert!(perturb_normal(Vec3::Z, tangent, red, 1.0).abs_diff_eq(Vec3::X, 1e-6));
+        assert!(perturb_normal(Vec3::Z, tangent, green, 1.0).abs_diff_eq(Vec3::Y, 1e-6));
+        assert!(perturb_normal(Vec3::Z, tangent * Vec4::new(1.0, 1.0, 1.0, -1.0), green, 1.0).abs_diff_eq(-Vec3::Y, 1e-6));
+        // without a tangent there is no frame to bend the normal in
+        assert_eq!(perturb_normal(Vec3::Z, Vec4::ZERO, red, 1.0), Vec3::Z);
+    }
+
+    #[test]
+    fn normal_scale_only_scales_the_tangent_plane() {
+        let tangent = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        // 45 degrees towards the tangent, halved
+        let texel = (Vec3::new(1.0, 0.0, 1.0).normalize() * 0.5 + 0.5).extend(1.0);
+        let normal = perturb_normal(Vec3::Z, tangent, texel, 0.5);
+        assert!(normal.abs_diff_eq(Vec3::new(0.5, 0.0, 1.0).normalize(), 1e-6), "{}", normal);
+    }
+}
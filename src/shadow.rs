@@ -0,0 +1,358 @@
+use crate::camera::View;
+use crate::framebuffer::{Framebuffer, unpack_depth};
+use crate::geometry::{Vertex, clip_to_screen};
+use crate::light::{Light, LightKind, Lighting};
+use crate::material::MaterialTextures;
+use crate::scene::{Scene, SceneShader, ShadingContext, render_scene_with};
+use crate::shader::{Fragment, FragmentShader, VertexOutput, VertexShader};
+use crate::utilities::coords_to_index;
+use glam::{Mat4, Vec3, Vec4};
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub const SHADOW_MAP_SIZE: usize = 1024;
+// texels on each side of the center one averaged by the lookup, 1 is a 3x3 kernel
+const PCF_RADIUS: i32 = 1;
+
+// Depth only pass: positions in the light's clip space and nothing to interpolate,
+// f32 being the cheapest type that satisfies Varyings. The color is never read.
+#[derive(Clone, Copy)]
+pub struct ShadowCasterShader {
+    pub mvp: Mat4,
+}
+
+impl VertexShader for ShadowCasterShader {
+    type Varyings = f32;
+
+    fn shade(&self, vertex: &Vertex) -> VertexOutput<f32> {
+        VertexOutput {
+            position: self.mvp * vertex.position,
+            varyings: 0.0,
+        }
+    }
+}
+
+impl FragmentShader<f32> for ShadowCasterShader {
+    fn shade(&self, _fragment: &Fragment<f32>) -> Option<Vec4> {
+        Some(Vec4::ZERO)
+    }
+}
+
+// Depth only pass over a scene, what shadow maps render.
+pub struct ShadowCasting;
+
+impl SceneShader for ShadowCasting {
+    type Vertex<'a> = ShadowCasterShader;
+    type Fragment<'a> = ShadowCasterShader;
+
+    fn shaders<'a>(&'a self, context: &ShadingContext<'a>) -> (ShadowCasterShader, ShadowCasterShader) {
+        let shader = ShadowCasterShader { mvp: context.view_projection * context.model };
+        (shader, shader)
+    }
+}
+
+// Scene depth as seen from a light, rendered by the regular tiled rasterizer into its own
+// Framebuffer. Only the depth buffer matters, it holds clip z like every other target.
+pub struct ShadowMap {
+    pub target: Framebuffer,
+    pub view_projection: Mat4,
+    // where the light looks from
+    pub position: Vec3,
+    // clip z per world unit away from the light, to turn the bias into depth
+    depth_scale: f32,
+    // world size of a texel, per unit of distance from the light for perspective maps
+    texel_size: f32,
+    perspective: bool,
+}
+
+// Projection of a shadow map and what its lookups need to know about it.
+struct Fit {
+    view_projection: Mat4,
+    position: Vec3,
+    depth_scale: f32,
+    texel_size: f32,
+    perspective: bool,
+}
+
+// Directional lights get an orthographic projection around the sphere, spot lights one covering their cone.
+fn fit(light: &Light, center: Vec3, radius: f32, size: usize) -> Option<Fit> {
+    let direction = light.direction.normalize_or_zero();
+    if !light.cast_shadows || direction == Vec3::ZERO {
+        return None;
+    }
+    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+    match light.kind {
+        LightKind::Directional => {
+            let position = center - direction * radius * 2.0;
+            let view = Mat4::look_at_rh(position, center, up);
+            let projection = Mat4::orthographic_rh(-radius, radius, -radius, radius, radius, radius * 3.0);
+            Some(Fit {
+                view_projection: projection * view,
+                position,
+                depth_scale: 1.0 / (2.0 * radius),
+                texel_size: 2.0 * radius / size as f32,
+                perspective: false,
+            })
+        }
+        LightKind::Spot { outer_cone, .. } => {
+            let far = if light.range.is_finite() {
+                light.range
+            } else {
+                (center - light.position).length() + radius
+            };
+            let near = (far * 1e-3).max(0.01);
+            let fov = (outer_cone * 2.0).clamp(0.01, 3.0);
+            let view = Mat4::look_at_rh(light.position, light.position + direction, up);
+            let projection = Mat4::perspective_rh(fov, 1.0, near, far);
+            Some(Fit {
+                view_projection: projection * view,
+                position: light.position,
+                depth_scale: far / (far - near),
+                texel_size: 2.0 * (fov / 2.0).tan() / size as f32,
+                perspective: true,
+            })
+        }
+        LightKind::Point => None,
+    }
+}
+
+impl ShadowMap {
+    // Fits a map around the sphere (`center`, `radius`) holding everything that casts or receives.
+    // None for point lights, lights with shadows off and lights without a direction.
+    pub fn for_light(light: &Light, center: Vec3, radius: f32, size: usize) -> Option<Self> {
+        let fit = fit(light, center, radius, size)?;
+        Some(Self {
+            target: Framebuffer::new(size, size),
+            view_projection: fit.view_projection,
+            position: fit.position,
+            depth_scale: fit.depth_scale,
+            texel_size: fit.texel_size,
+            perspective: fit.perspective,
+        })
+    }
+
+    // Like for_light, but keeps this map's target. False when the light casts no shadow.
+    pub fn refit(&mut self, light: &Light, center: Vec3, radius: f32) -> bool {
+        let Some(fit) = fit(light, center, radius, self.target.width) else {
+            return false;
+        };
+        self.view_projection = fit.view_projection;
+        self.position = fit.position;
+        self.depth_scale = fit.depth_scale;
+        self.texel_size = fit.texel_size;
+        self.perspective = fit.perspective;
+        true
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            view_projection: self.view_projection,
+            position: self.position,
+        }
+    }
+
+    // Renders the depth of every instance of the scene, replacing what the map held.
+    pub fn render(&self, scene: &Scene) {
+        self.target.clear(0);
+        render_scene_with(scene, &self.view(), &Lighting::default(), &MaterialTextures::default(), &self.target, &ShadowCasting, false);
+    }
+
+    // Fraction of the light reaching `position`, percentage closer filtered over neighbouring texels.
+    // Anything outside the map counts as lit.
+    pub fn visibility(&self, position: Vec3, normal: Vec3) -> f32 {
+        let distance = (self.view_projection * position.extend(1.0)).w.max(0.0);
+        let texel = if self.perspective { self.texel_size * distance } else { self.texel_size };
+
+        // looking up a little off the surface keeps it from shadowing itself (acne)
+        let clip = self.view_projection * (position + normal * texel * 1.5).extend(1.0);
+        if clip.w <= 0.0 {
+            return 1.0;
+        }
+        let ndc = clip.truncate() / clip.w;
+        if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 || ndc.z > 1.0 {
+            return 1.0;
+        }
+
+        let screen = clip_to_screen(clip, self.target.size());
+        let depth = clip.z - texel * self.depth_scale;
+        let (x, y) = (screen.x.floor() as i32, screen.y.floor() as i32);
+        let (width, height) = (self.target.width as i32, self.target.height as i32);
+
+        let mut lit = 0.0;
+        for dy in -PCF_RADIUS..=PCF_RADIUS {
+            for dx in -PCF_RADIUS..=PCF_RADIUS {
+                let sx = (x + dx).clamp(0, width - 1) as usize;
+                let sy = (y + dy).clamp(0, height - 1) as usize;
+                let stored = unpack_depth(self.target.z_buffer[coords_to_index(sx, sy, self.target.width)].load(Ordering::Relaxed));
+                if depth <= stored {
+                    lit += 1.0;
+                }
+            }
+        }
+        let taps = (2 * PCF_RADIUS + 1) * (2 * PCF_RADIUS + 1);
+        lit / taps as f32
+    }
+}
+
+// Shadow maps by light index, kept from frame to frame like a Framebuffer keeps its tiles:
+// refitting a map to the scene clears its target in place instead of allocating a new one.
+pub struct ShadowMaps {
+    size: usize,
+    maps: Mutex<Vec<Option<ShadowMap>>>,
+}
+
+impl ShadowMaps {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            maps: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Fits a map to every light around the sphere and renders the scene's depth into it.
+    // The guard holds one entry per light, None where the light casts no shadow.
+    pub fn update(&self, scene: &Scene, lights: &[Light], center: Vec3, radius: f32) -> MutexGuard<'_, Vec<Option<ShadowMap>>> {
+        let mut maps = self.maps.lock().unwrap_or_else(PoisonError::into_inner);
+        maps.resize_with(lights.len(), || None);
+
+        for (map, light) in maps.iter_mut().zip(lights) {
+            let kept = match map {
+                Some(shadow) => shadow.refit(light, center, radius),
+                None => false,
+            };
+            if !kept {
+                *map = ShadowMap::for_light(light, center, radius, self.size);
+            }
+            if let Some(shadow) = map {
+                shadow.render(scene);
+            }
+        }
+        maps
+    }
+}
+
+impl Default for ShadowMaps {
+    fn default() -> Self {
+        Self::new(SHADOW_MAP_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::RendererCamera;
+    use crate::geometry::MeshRenderer;
+    use crate::scene::{Instance, Shading, render_scene};
+    use crate::transform::Transform;
+    use glam::{UVec3, Vec2, vec3};
+
+    // Square of side 2 * half at height y, facing up.
+    fn square(half: f32, y: f32) -> MeshRenderer {
+        let corners = [vec3(-half, y, half), vec3(half, y, half), vec3(half, y, -half), vec3(-half, y, -half)];
+        let vertices = corners.map(|p| Vertex::new(p.extend(1.0), Vec3::Y, Vec3::ONE, Vec2::ZERO));
+        MeshRenderer::from_vertices(&[UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)], &vertices)
+    }
+
+    // A floor with a smaller square floating above it.
+    fn scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.meshes = vec![square(4.0, 0.0), square(1.0, 1.0)];
+        scene.instances = vec![
+            Instance::new("floor", 0, None, Transform::IDENTITY),
+            Instance::new("caster", 1, None, Transform::IDENTITY),
+        ];
+        scene
+    }
+
+    // The scene seen from straight above.
+    fn render(scene: &Scene, lights: &[Light], shadows: Option<&ShadowMaps>) -> Vec<u32> {
+        let camera = RendererCamera {
+            aspect_ratio: 1.0,
+            transform: Transform::from_translation_rotation(vec3(0.0, 8.0, 0.01), glam::Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
+            ..Default::default()
+        };
+        let framebuffer = Framebuffer::new(64, 64);
+        framebuffer.clear(0);
+        let lighting = Lighting { lights, environment: None, shadows };
+        render_scene(scene, &camera, &lighting, &MaterialTextures::default(), &framebuffer, Shading::BlinnPhong);
+        framebuffer.buffer.iter().map(|p| p.load(Ordering::Relaxed)).collect()
+    }
+
+    #[test]
+    fn casters_shadow_what_is_below_them() {
+        let light = Light::directional(-Vec3::Y, Vec3::ONE, 1.0);
+        let shadow = ShadowMap::for_light(&light, Vec3::ZERO, 6.0, 256).unwrap();
+        shadow.render(&scene());
+
+        assert_eq!(shadow.visibility(Vec3::ZERO, Vec3::Y), 0.0);
+        assert_eq!(shadow.visibility(vec3(3.0, 0.0, -3.0), Vec3::Y), 1.0);
+        // lit surfaces don't shadow themselves
+        assert_eq!(shadow.visibility(vec3(0.5, 1.0, 0.5), Vec3::Y), 1.0);
+        assert_eq!(shadow.visibility(vec3(-2.0, 0.0, 2.0), Vec3::Y), 1.0);
+        // the filtered lookup softens the shadow's edge
+        let edge = shadow.visibility(vec3(1.0, 0.0, 0.0), Vec3::Y);
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+    }
+
+    #[test]
+    fn only_directional_and_spot_lights_get_maps() {
+        let spot = Light::spot(vec3(0.0, 3.0, 0.0), -Vec3::Y, Vec3::ONE, 1.0, 10.0, 0.3, 0.5);
+        let point = Light::point(vec3(0.0, 3.0, 0.0), Vec3::ONE, 1.0, 10.0);
+        let unshadowed = Light { cast_shadows: false, ..Light::directional(-Vec3::Y, Vec3::ONE, 1.0) };
+
+        assert!(ShadowMap::for_light(&spot, Vec3::ZERO, 6.0, 64).is_some());
+        assert!(ShadowMap::for_light(&point, Vec3::ZERO, 6.0, 64).is_none());
+        assert!(ShadowMap::for_light(&unshadowed, Vec3::ZERO, 6.0, 64).is_none());
+    }
+
+    #[test]
+    fn lit_shading_darkens_shadowed_pixels() {
+        let scene = scene();
+        let brightness = |light: Light| render(&scene, &[light], None).iter().map(|p| p & 0xFF).sum::<u32>();
+
+        // slanted, so the shadow falls next to the caster where the camera sees it
+        let light = Light::directional(vec3(1.0, -1.0, 0.5), Vec3::ONE, 0.5);
+        assert!(brightness(light) < brightness(Light { cast_shadows: false, ..light }));
+    }
+
+    #[test]
+    fn shadow_maps_are_kept_between_frames() {
+        let scene = scene();
+        let lights = [Light::directional(vec3(1.0, -1.0, 0.5), Vec3::ONE, 0.5)];
+        let shadows = ShadowMaps::default();
+
+        let first = render(&scene, &lights, Some(&shadows));
+        let target = shadows.maps.lock().unwrap()[0].as_ref().unwrap().target.z_buffer.as_ptr();
+        let second = render(&scene, &lights, Some(&shadows));
+
+        // cleared and redrawn in place, not reallocated
+        assert_eq!(target, shadows.maps.lock().unwrap()[0].as_ref().unwrap().target.z_buffer.as_ptr());
+        assert_eq!(first, second);
+        // and the same as maps made for a single frame
+        assert_eq!(first, render(&scene, &lights, None));
+    }
+
+    #[test]
+    fn shadow_maps_follow_the_lights() {
+        let scene = scene();
+        let shadows = ShadowMaps::new(64);
+        let lights = [
+            Light::directional(vec3(0.0, -1.0, 0.0), Vec3::ONE, 1.0),
+            Light::point(vec3(0.0, 3.0, 0.0), Vec3::ONE, 1.0, 10.0),
+        ];
+        render(&scene, &lights, Some(&shadows));
+        {
+            let maps = shadows.maps.lock().unwrap();
+            assert_eq!(maps.len(), 2);
+            assert!(maps[0].is_some() && maps[1].is_none());
+        }
+
+        render(&scene, &lights[..1], Some(&shadows));
+        assert_eq!(shadows.maps.lock().unwrap().len(), 1);
+    }
+}
@@ -1,55 +1,491 @@
+use crate::error::{Error, Result};
 use crate::utilities::*;
+use crate::sampler::{Filter, Sampler};
+use glam::{Vec2, Vec3, Vec4};
 use stb_image;
 use std::path::Path;
 
+// One level of the mip chain, each half the size of the previous one.
+pub struct MipLevel {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u32>,
+}
+
 pub struct Texture {
     pub width: usize,
     pub height: usize,
     pub data: Vec<u32>,
+    // linear float texels, only filled for HDR sources (.hdr, float glTF images);
+    // `data` then holds the same image clamped to [0, 1]
+    pub hdr: Vec<Vec4>,
+    // levels 1.. down to 1x1, level 0 is `data`
+    pub mips: Vec<MipLevel>,
+    pub sampler: Sampler,
 }
 
 impl Texture {
-    pub fn load(path: &Path) -> Self {
-        let decoded_image = stb_image::image::load(path);
-        if let stb_image::image::LoadResult::ImageU8(image) = decoded_image {
-            let data = (0..image.data.len() / 3)
-                .map(|id| {
-                    to_argb(
-                        255,
-                        image.data[id * 3],
-                        image.data[id * 3 + 1],
-                        image.data[id * 3 + 2],
-                    )
-                })
-                .collect();
-            Self {
-                width: image.width,
-                height: image.height,
-                data,
+    pub fn from_argb(width: usize, height: usize, data: Vec<u32>) -> Self {
+        let mips = generate_mips(width, height, &data);
+        Self {
+            width,
+            height,
+            data,
+            hdr: Vec::new(),
+            mips,
+            sampler: Sampler::default(),
+        }
+    }
+
+    pub fn from_hdr(width: usize, height: usize, hdr: Vec<Vec4>) -> Self {
+        let mut texture = Self::from_argb(width, height, hdr.iter().map(|texel| argb_from_vec4(*texel)).collect());
+        texture.hdr = hdr;
+        texture
+    }
+
+    pub fn mip_count(&self) -> usize {
+        self.mips.len() + 1
+    }
+
+    // (width, height, texels) of a mip level, 0 being the full image.
+    pub fn level(&self, level: usize) -> (usize, usize, &[u32]) {
+        match level {
+            0 => (self.width, self.height, &self.data),
+            _ => {
+                let mip = &self.mips[level - 1];
+                (mip.width, mip.height, &mip.data)
+            }
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        !self.hdr.is_empty()
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        // read ourselves so a missing file is an io error, not an stb message
+        let bytes = std::fs::read(path)?;
+        match stb_image::image::load_from_memory(&bytes) {
+            stb_image::image::LoadResult::ImageU8(image) => {
+                check_channels(image.depth)?;
+                let data = image.data.chunks_exact(image.depth).map(argb_from_channels).collect();
+                Ok(Self::from_argb(image.width, image.height, data))
+            }
+            // stb decodes .hdr (radiance) files as floats, everything else as 8 bit
+            stb_image::image::LoadResult::ImageF32(image) => {
+                check_channels(image.depth)?;
+                let hdr = image.data.chunks_exact(image.depth).map(vec4_from_channels).collect();
+                Ok(Self::from_hdr(image.width, image.height, hdr))
+            }
+            stb_image::image::LoadResult::Error(message) => Err(Error::Decode(message)),
+        }
+    }
+
+    // Decoded images from gltf::import, converted to our packed ARGB.
+    pub fn from_gltf_image(image: &gltf::image::Data) -> Self {
+        use gltf::image::Format;
+
+        // 16 bit formats are reduced to 8 bits per channel, floats keep their range in `hdr`
+        let (channels, bytes_per_channel) = match image.format {
+            Format::R8 => (1, 1),
+            Format::R8G8 => (2, 1),
+            Format::R8G8B8 => (3, 1),
+            Format::R8G8B8A8 => (4, 1),
+            Format::R16 => (1, 2),
+            Format::R16G16 => (2, 2),
+            Format::R16G16B16 => (3, 2),
+            Format::R16G16B16A16 => (4, 2),
+            Format::R32G32B32FLOAT => (3, 4),
+            Format::R32G32B32A32FLOAT => (4, 4),
+        };
+        let (width, height) = (image.width as usize, image.height as usize);
+        let pixels = image.pixels.chunks_exact(channels * bytes_per_channel);
+
+        match bytes_per_channel {
+            1 => Self::from_argb(width, height, pixels.map(argb_from_channels).collect()),
+            2 => {
+                let data = pixels
+                    .map(|pixel| {
+                        let mut texel = [0u8; 4];
+                        for (c, bytes) in pixel.chunks_exact(2).enumerate() {
+                            texel[c] = (u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8;
+                        }
+                        argb_from_channels(&texel[..channels])
+                    })
+                    .collect();
+                Self::from_argb(width, height, data)
+            }
+            _ => {
+                let hdr = pixels
+                    .map(|pixel| {
+                        let texel: Vec<f32> = pixel
+                            .chunks_exact(4)
+                            .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                            .collect();
+                        vec4_from_channels(&texel)
+                    })
+                    .collect();
+                Self::from_hdr(width, height, hdr)
             }
-        } else {
-            panic!("Unsupported texture type");
         }
     }
 
     pub fn argb_at_uv(&self, u: f32, v: f32) -> u32 {
-        // Claude Sonnet 4.5 improved
-        // glTF default sampler wrapping is REPEAT. Using rem_euclid keeps negatives sane too.
-        let u = u.rem_euclid(1.0);
-        let v = v.rem_euclid(1.0);
+        let x = self.sampler.wrap_u.apply((u * self.width as f32).floor() as i32, self.width);
+        let y = self.sampler.wrap_v.apply((v * self.height as f32).floor() as i32, self.height);
+        self.data[coords_to_index(x, y, self.width)]
+    }
 
-        let u = u * (self.width - 1) as f32;
-        let v = v * (self.height - 1) as f32;
+    // Filtered lookup. `duv_dx` and `duv_dy` are how much uv changes to the next pixel
+    // right and down, they pick the mip level when the texture is minified.
+    pub fn sample(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> u32 {
+        match self.sampler.filter {
+            Filter::Nearest => self.argb_at_uv(uv.x, uv.y),
+            _ => argb_from_vec4(self.sample_rgba(uv, duv_dx, duv_dy)),
+        }
+    }
 
-        let x = u as usize;
-        let y = v as usize;
+    // Same as sample, as rgba in [0, 1] for shaders to do math on.
+    pub fn sample_rgba(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> Vec4 {
+        match self.sampler.filter {
+            Filter::Nearest => vec4_from_argb(self.argb_at_uv(uv.x, uv.y)),
+            Filter::Bilinear => self.bilinear(0, uv),
+            Filter::Trilinear => self.trilinear(uv, self.lod(duv_dx, duv_dy)),
+            Filter::Anisotropic => self.anisotropic(uv, duv_dx, duv_dy),
+        }
+    }
 
-        let id = coords_to_index(x, y, self.width);
+    pub fn trilinear(&self, uv: Vec2, lod: f32) -> Vec4 {
+        let lod = lod.clamp(0.0, (self.mip_count() - 1) as f32);
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+        if t == 0.0 || level + 1 >= self.mip_count() {
+            self.bilinear(level, uv)
+        } else {
+            self.bilinear(level, uv).lerp(self.bilinear(level + 1, uv), t)
+        }
+    }
 
-        if id < self.data.len() {
-            self.data[id]
+    // The footprint of a pixel is roughly a parallelogram spanned by the two derivatives.
+    // Instead of one blurry lookup sized for its long side, take up to max_anisotropy
+    // lookups sized for its short side, spread along the long one.
+    pub fn anisotropic(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> Vec4 {
+        let size = glam::vec2(self.width as f32, self.height as f32);
+        let (len_x, len_y) = ((duv_dx * size).length(), (duv_dy * size).length());
+        let (major_uv, major, minor) = if len_x > len_y {
+            (duv_dx, len_x, len_y)
         } else {
-            to_argb(255, 255, 0, 255)
+            (duv_dy, len_y, len_x)
+        };
+
+        let max_taps = self.sampler.max_anisotropy.max(1) as f32;
+        let taps = (major / minor.max(f32::MIN_POSITIVE)).ceil().clamp(1.0, max_taps);
+        let lod = (major / taps).max(f32::MIN_POSITIVE).log2();
+
+        let mut sum = Vec4::ZERO;
+        for i in 0..taps as u32 {
+            let offset = (i as f32 + 0.5) / taps - 0.5;
+            sum += self.trilinear(uv + major_uv * offset, lod);
+        }
+        sum / taps
+    }
+
+    // log2 of the pixel footprint in texels, 0 means one texel per pixel.
+    pub fn lod(&self, duv_dx: Vec2, duv_dy: Vec2) -> f32 {
+        let size = glam::vec2(self.width as f32, self.height as f32);
+        let footprint = (duv_dx * size).length().max((duv_dy * size).length());
+        footprint.max(f32::MIN_POSITIVE).log2()
+    }
+
+    fn texel(&self, level: usize, x: i32, y: i32) -> Vec4 {
+        let (width, height, data) = self.level(level);
+        let x = self.sampler.wrap_u.apply(x, width);
+        let y = self.sampler.wrap_v.apply(y, height);
+        vec4_from_argb(data[coords_to_index(x, y, width)])
+    }
+
+    // 4 nearest texels weighted by distance, texel centers sit at half integers.
+    pub fn bilinear(&self, level: usize, uv: Vec2) -> Vec4 {
+        let (width, height, _) = self.level(level);
+        let x = uv.x * width as f32 - 0.5;
+        let y = uv.y * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.texel(level, x0, y0).lerp(self.texel(level, x0 + 1, y0), fx);
+        let bottom = self.texel(level, x0, y0 + 1).lerp(self.texel(level, x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+// Box filters each level down to 1x1. Odd sizes repeat the last row/column.
+fn generate_mips(width: usize, height: usize, data: &[u32]) -> Vec<MipLevel> {
+    let mut mips: Vec<MipLevel> = Vec::new();
+    let (mut width, mut height) = (width, height);
+
+    while width > 1 || height > 1 {
+        let source = mips.last().map_or(data, |mip| &mip.data[..]);
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+
+        let mut next = Vec::with_capacity(next_width * next_height);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                let (x0, y0) = (2 * x, 2 * y);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let sum = vec4_from_argb(source[coords_to_index(x0, y0, width)])
+                    + vec4_from_argb(source[coords_to_index(x1, y0, width)])
+                    + vec4_from_argb(source[coords_to_index(x0, y1, width)])
+                    + vec4_from_argb(source[coords_to_index(x1, y1, width)]);
+                next.push(argb_from_vec4(sum * 0.25));
+            }
+        }
+
+        mips.push(MipLevel {
+            width: next_width,
+            height: next_height,
+            data: next,
+        });
+        (width, height) = (next_width, next_height);
+    }
+
+    mips
+}
+
+fn check_channels(channels: usize) -> Result<()> {
+    if (1..=4).contains(&channels) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedPixelFormat(format!("{} channels", channels)))
+    }
+}
+
+/// Packs 1 (gray), 2 (gray, alpha), 3 (rgb) or 4 (rgba) channels into ARGB.
+///
+/// ```
+/// use ruster::texture::argb_from_channels;
+///
+/// assert_eq!(argb_from_channels(&[10]), 0xFF0A0A0A);
+/// assert_eq!(argb_from_channels(&[1, 2, 3, 128]), 0x80010203);
+/// ```
+pub fn argb_from_channels(channels: &[u8]) -> u32 {
+    match *channels {
+        [l] => to_argb(255, l, l, l),
+        [l, a] => to_argb(a, l, l, l),
+        [r, g, b] => to_argb(255, r, g, b),
+        [r, g, b, a] => to_argb(a, r, g, b),
+        _ => to_argb(255, 255, 0, 255),
+    }
+}
+
+// Same channel layouts as argb_from_channels, as an rgba float texel.
+pub fn vec4_from_channels(channels: &[f32]) -> Vec4 {
+    match *channels {
+        [l] => Vec4::new(l, l, l, 1.0),
+        [l, a] => Vec4::new(l, l, l, a),
+        [r, g, b] => Vec4::new(r, g, b, 1.0),
+        [r, g, b, a] => Vec4::new(r, g, b, a),
+        _ => Vec4::new(1.0, 0.0, 1.0, 1.0),
+    }
+}
+
+pub fn vec4_from_argb(argb: u32) -> Vec4 {
+    let channel = |shift: u32| ((argb >> shift) & 0xFF) as f32 / 255.0;
+    Vec4::new(channel(16), channel(8), channel(0), channel(24))
+}
+
+pub fn argb_from_vec4(texel: Vec4) -> u32 {
+    let texel = (texel.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+    to_argb(texel.w as u8, texel.x as u8, texel.y as u8, texel.z as u8)
+}
+
+// Base color and emissive maps are stored sRGB encoded, lighting math wants linear values.
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    let channel = |c: f32| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    Vec3::new(channel(color.x), channel(color.y), channel(color.z))
+}
+
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+    let channel = |c: f32| if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    let color = color.clamp(Vec3::ZERO, Vec3::ONE);
+    Vec3::new(channel(color.x), channel(color.y), channel(color.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{TempDir, material_triangle_gltf, png};
+    use crate::sampler::Wrap;
+    use crate::scene::Scene;
+    use glam::vec2;
+    use png::{BitDepth, ColorType};
+
+    // The same file read directly and as the base color of a glTF, whose import decodes it on its own.
+    fn load_both_ways(name: &str, file: &str, bytes: &[u8]) -> (Texture, Texture) {
+        let dir = TempDir::new(name);
+        let loaded = Texture::load(&dir.write(file, bytes)).unwrap();
+        let assets = format!(
+            r#""materials": [{{ "pbrMetallicRoughness": {{ "baseColorTexture": {{ "index": 0 }} }} }}],
+            "textures": [{{ "source": 0 }}],
+            "images": [{{ "uri": "{}" }}]"#,
+            file
+        );
+        let mut scene = Scene::load_gltf(&material_triangle_gltf(&dir, r#"[{ "mesh": 0 }]"#, &assets)).unwrap();
+        (loaded, scene.textures.remove(0))
+    }
+
+    #[test]
+    fn gray_gray_alpha_and_rgba_pngs_are_expanded_to_argb() {
+        let cases: [(ColorType, &[u8], [u32; 2]); 3] = [
+            (ColorType::Grayscale, &[10, 200], [0xFF0A0A0A, 0xFFC8C8C8]),
+            (ColorType::GrayscaleAlpha, &[10, 255, 200, 128], [0xFF0A0A0A, 0x80C8C8C8]),
+            (ColorType::Rgba, &[1, 2, 3, 255, 4, 5, 6, 0], [0xFF010203, 0x00040506]),
+        ];
+        for (color, pixels, expected) in cases {
+            let (loaded, imported) = load_both_ways("texture-channels", "image.png", &png(2, 1, color, BitDepth::Eight, pixels));
+            for texture in [loaded, imported] {
+                assert_eq!((texture.width, texture.height), (2, 1));
+                assert_eq!(texture.data, expected, "{:?}", color);
+                assert!(!texture.is_hdr());
+            }
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_pngs_keep_their_high_byte() {
+        // big endian channels: 0x1234, 0xABCD, 0xFF00
+        let bytes = png(1, 1, ColorType::Rgb, BitDepth::Sixteen, &[0x12, 0x34, 0xAB, 0xCD, 0xFF, 0x00]);
+        let (loaded, imported) = load_both_ways("texture-16bit", "image.png", &bytes);
+        assert_eq!(loaded.data, [0xFF12ABFF]);
+        assert_eq!(imported.data, [0xFF12ABFF]);
+    }
+
+    #[test]
+    fn radiance_hdr_files_load_as_float_textures() {
+        // used to panic, stb hands .hdr files back as floats.
+        // flat rgbe pixels: mantissas over 256 scaled by 2^(exponent - 128)
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 0, 129, 128, 128, 128, 131]);
+        let dir = TempDir::new("texture-hdr");
+        let texture = Texture::load(&dir.write("sky.hdr", bytes)).unwrap();
+
+        assert!(texture.is_hdr());
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(texture.hdr, [Vec4::new(1.0, 0.5, 0.0, 1.0), Vec4::new(4.0, 4.0, 4.0, 1.0)]);
+        // the 8 bit copy is clamped
+        assert_eq!(texture.data, [0xFFFF8000, 0xFFFFFFFF]);
+    }
+
+    #[test]
+    fn float_gltf_images_keep_their_range() {
+        use gltf::image::{Data, Format};
+
+        let texel = |values: &[f32]| values.iter().flat_map(|v| v.to_ne_bytes()).collect::<Vec<u8>>();
+        let rgb = Data { pixels: texel(&[2.0, 0.5, -1.0]), format: Format::R32G32B32FLOAT, width: 1, height: 1 };
+        let rgba = Data { pixels: texel(&[0.0, 0.0, 1.0, 0.25]), format: Format::R32G32B32A32FLOAT, width: 1, height: 1 };
+
+        let texture = Texture::from_gltf_image(&rgb);
+        assert_eq!(texture.hdr, [Vec4::new(2.0, 0.5, -1.0, 1.0)]);
+        assert_eq!(texture.data, [0xFFFF8000]);
+        let texture = Texture::from_gltf_image(&rgba);
+        assert_eq!(texture.hdr, [Vec4::new(0.0, 0.0, 1.0, 0.25)]);
+        assert_eq!(texture.data, [0x400000FF]);
+    }
+
+    #[test]
+    fn odd_sizes_mip_down_to_one_texel() {
+        let texture = Texture::from_argb(5, 3, vec![0xFF336699; 15]);
+        assert_eq!(texture.mip_count(), 3);
+        let sizes: Vec<(usize, usize)> = (0..texture.mip_count())
+            .map(|level| {
+                let (width, height, data) = texture.level(level);
+                assert_eq!(data.len(), width * height);
+                // the box filter doesn't drift on flat colors
+                assert!(data.iter().all(|&texel| texel == 0xFF336699));
+                (width, height)
+            })
+            .collect();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn lod_follows_the_pixel_footprint() {
+        let texture = Texture::from_argb(8, 4, vec![0; 32]);
+        // one texel per pixel along both axes
+        let (dx, dy) = (vec2(1.0 / 8.0, 0.0), vec2(0.0, 1.0 / 4.0));
+        assert_eq!(texture.lod(dx, dy), 0.0);
+        assert_eq!(texture.lod(dx * 2.0, dy), 1.0);
+        // the longer side of the footprint picks the level
+        assert_eq!(texture.lod(dx, dy * 4.0), 2.0);
+        assert!(texture.lod(dx * 3.0, dy) > 1.0 && texture.lod(dx * 3.0, dy) < 2.0);
+    }
+
+    #[test]
+    fn trilinear_averages_the_texels_under_the_footprint() {
+        // 2x2 black and white checker
+        let texture = Texture::from_argb(2, 2, vec![0xFF000000, 0xFFFFFFFF, 0xFFFFFFFF, 0xFF000000]);
+        let texel_center = vec2(0.25, 0.25);
+        let (dx, dy) = (vec2(0.5, 0.0), vec2(0.0, 0.5));
+        assert_eq!(texture.sample(texel_center, dx, dy), 0xFF000000);
+        // a footprint of the whole checker reads the gray 1x1 level
+        assert_eq!(texture.sample(texel_center, dx * 2.0, dy * 2.0), 0xFF808080);
+    }
+
+    #[test]
+    fn wrap_modes_apply_per_axis() {
+        // 0 1
+        // 2 3
+        let mut texture = Texture::from_argb(2, 2, vec![0, 1, 2, 3]);
+        texture.sampler = Sampler {
+            filter: Filter::Nearest,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::ClampToEdge,
+            ..Default::default()
+        };
+        // one texel past the bottom right corner
+        assert_eq!(texture.argb_at_uv(1.25, 1.25), 2);
+        assert_eq!(texture.argb_at_uv(-0.25, -0.25), 1);
+
+        texture.sampler.wrap_u = Wrap::MirroredRepeat;
+        assert_eq!(texture.argb_at_uv(1.25, 1.25), 3);
+        assert_eq!(texture.argb_at_uv(-0.25, -0.25), 0);
+    }
+
+    // 8x8 of one texel wide black and white stripes, running down (columns) or across (rows).
+    fn stripes(columns: bool) -> Texture {
+        let data = (0..64)
+            .map(|i| {
+                let (x, y) = (i % 8, i / 8);
+                if (if columns { x } else { y }) % 2 == 0 { 0xFF000000 } else { 0xFFFFFFFF }
+            })
+            .collect();
+        Texture::from_argb(8, 8, data)
+    }
+
+    #[test]
+    fn anisotropic_taps_run_along_the_major_axis() {
+        // a footprint one texel wide and four texels tall
+        let (dx, dy) = (vec2(1.0 / 8.0, 0.0), vec2(0.0, 4.0 / 8.0));
+        let uv = vec2(0.5 / 8.0, 0.5 / 8.0);
+
+        // taps stay inside the black column, trilinear blurs over the whole footprint
+        let columns = stripes(true);
+        assert_eq!(argb_from_vec4(columns.anisotropic(uv, dx, dy)), 0xFF000000);
+        assert_eq!(argb_from_vec4(columns.trilinear(uv, columns.lod(dx, dy))), 0xFF808080);
+        // across the rows the taps do cover both colors
+        let rows = stripes(false);
+        assert_eq!(argb_from_vec4(rows.anisotropic(uv, dx, dy)), 0xFF808080);
+    }
+
+    #[test]
+    fn isotropic_footprints_are_one_trilinear_tap() {
+        let texture = stripes(true);
+        for scale in [0.5, 1.0, 3.0, 8.0] {
+            let (dx, dy) = (vec2(scale / 8.0, 0.0), vec2(0.0, scale / 8.0));
+            for uv in [vec2(0.1, 0.7), vec2(0.33, 0.5), vec2(0.9, 0.05)] {
+                let trilinear = texture.trilinear(uv, texture.lod(dx, dy));
+                assert_eq!(texture.anisotropic(uv, dx, dy), trilinear, "{} {}", scale, uv);
+            }
         }
     }
 }
@@ -1,7 +1,9 @@
-use crate::{geometry::MeshRenderer, window::SCREEN_WIDTH};
-use bevy::prelude::ops::abs;
-use glam::{Vec2, Vec3};
+use crate::error::{Error, Result};
+use crate::geometry::{MeshRenderer, Tile};
+use glam::{I64Vec2, Vec2, Vec3};
 use std::cmp::max;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::sync::atomic::AtomicU32;
 
@@ -9,6 +11,42 @@ pub fn edge_function(v0: Vec2, v1: Vec2, point: Vec2) -> f32 {
     (point.x - v0.x) * (v1.y - v0.y) - (point.y - v0.y) * (v1.x - v0.x)
 }
 
+// Screen positions are snapped to 1/256 of a pixel before rasterizing, so edge tests are exact.
+pub const SUBPIXEL_BITS: u32 = 8;
+pub const SUBPIXEL_SCALE: f32 = (1 << SUBPIXEL_BITS) as f32;
+
+pub fn to_fixed(point: Vec2) -> I64Vec2 {
+    (point * SUBPIXEL_SCALE).round().as_i64vec2()
+}
+
+pub fn from_fixed(point: I64Vec2) -> Vec2 {
+    point.as_vec2() / SUBPIXEL_SCALE
+}
+
+// edge_function on snapped positions, in 1/SUBPIXEL_SCALE^2 pixels. i64 so it can't overflow.
+pub fn edge_function_fixed(v0: I64Vec2, v1: I64Vec2, point: I64Vec2) -> i64 {
+    (point.x - v0.x) * (v1.y - v0.y) - (point.y - v0.y) * (v1.x - v0.x)
+}
+
+/// Top-left fill rule: a pixel center exactly on an edge only belongs to the triangle
+/// if that edge is a top or a left one. For the front facing winding with y down that is
+/// an edge going down the screen, or a horizontal one going left.
+/// The neighbour sharing the edge walks it the other way, so exactly one of them gets the pixel.
+///
+/// ```
+/// use glam::i64vec2;
+/// use ruster::utilities::is_top_left;
+///
+/// let (a, b) = (i64vec2(0, 0), i64vec2(256, 512));
+/// assert!(is_top_left(a, b) != is_top_left(b, a));
+/// let (c, d) = (i64vec2(512, 0), i64vec2(0, 0));
+/// assert!(is_top_left(c, d) && !is_top_left(d, c));
+/// ```
+pub fn is_top_left(from: I64Vec2, to: I64Vec2) -> bool {
+    let edge = to - from;
+    edge.y > 0 || (edge.y == 0 && edge.x < 0)
+}
+
 pub fn barycentric_coordinates(
     point: Vec2,
     v0: Vec2,
@@ -16,16 +54,23 @@ pub fn barycentric_coordinates(
     v2: Vec2,
     area: f32,
 ) -> Option<Vec3> {
+    let bary = barycentric_weights(point, v0, v1, v2, area);
+    if bary.x >= 0.0 && bary.y >= 0.0 && bary.z >= 0.0 {
+        Some(bary)
+    } else {
+        None
+    }
+}
+
+// Same weights without the inside test, points outside get negative ones.
+// Used for the helper pixels next to a fragment when taking derivatives.
+pub fn barycentric_weights(point: Vec2, v0: Vec2, v1: Vec2, v2: Vec2, area: f32) -> Vec3 {
     let m0 = edge_function(point, v1, v2);
     let m1 = edge_function(point, v2, v0);
     let m2 = edge_function(point, v0, v1);
     // instead of 3 divisions we can do 1/area *
     let a = 1.0 / area;
-    if m0 >= 0.0 && m1 >= 0.0 && m2 >= 0.0 {
-        Some(glam::vec3(m0 * a, m1 * a, m2 * a))
-    } else {
-        None
-    }
+    glam::vec3(m0 * a, m1 * a, m2 * a)
 }
 
 pub fn to_argb(a: u8, r: u8, g: u8, b: u8) -> u32 {
@@ -62,8 +107,8 @@ where
     buffer.iter_mut().map(|x| *x = value).count();
 }
 
-pub fn load_gltf(path: &Path) -> MeshRenderer {
-    let (document, buffers, _images) = gltf::import(path).unwrap();
+pub fn load_gltf(path: &Path) -> Result<MeshRenderer> {
+    let (document, buffers, _images) = gltf::import(path)?;
 
     for scene in document.scenes() {
         for node in scene.nodes() {
@@ -73,7 +118,7 @@ pub fn load_gltf(path: &Path) -> MeshRenderer {
         }
     }
 
-    MeshRenderer::new()
+    Err(Error::NoMeshes)
 }
 
 // Credit: Claude Sonnet 4.5
@@ -97,20 +142,59 @@ pub fn convert_framebuffer_to_image(framebuffer: &[AtomicU32], image_data: &mut
     }
 }
 
-pub fn bresenham_line(buffer: &[AtomicU32], color: u32, x1: f32, y1: f32, x2: f32, y2: f32) {
-    let dx = x2 - x1;
-    let dy = y2 - y1;
+// Binary PPM (P6): a tiny header followed by raw RGB triplets, alpha is dropped.
+pub fn write_ppm(framebuffer: &[AtomicU32], width: usize, height: usize, path: &Path) -> std::io::Result<()> {
+    let mut rgba = vec![0u8; width * height * 4];
+    convert_framebuffer_to_image(framebuffer, &mut rgba);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in rgba.chunks_exact(4) {
+        writer.write_all(&pixel[..3])?;
+    }
+    writer.flush()
+}
+
+pub fn write_png(framebuffer: &[AtomicU32], width: usize, height: usize, path: &Path) -> std::io::Result<()> {
+    let mut rgba = vec![0u8; width * height * 4];
+    convert_framebuffer_to_image(framebuffer, &mut rgba);
+
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgba)?;
+    Ok(())
+}
+
+pub fn bresenham_line(buffer: &[AtomicU32], viewport_size: Vec2, color: u32, x1: f32, y1: f32, x2: f32, y2: f32) {
+    let screen = Tile::from_viewport(viewport_size);
+    bresenham_line_in_rect(buffer, viewport_size, &screen, color, glam::vec2(x1, y1), glam::vec2(x2, y2));
+}
 
-    let steps = f32::max(abs(dx), abs(dy));
+// Only plots the part of the line inside `rect`, lines may start or end off screen.
+pub fn bresenham_line_in_rect(buffer: &[AtomicU32], viewport_size: Vec2, rect: &Tile, color: u32, from: Vec2, to: Vec2) {
+    let dx = to.x - from.x;
+    let dy = to.y - from.y;
+
+    let steps = f32::max(dx.abs(), dy.abs());
     let x_inc = dx / steps;
     let y_inc = dy / steps;
 
-    let mut x = x1;
-    let mut y = y1;
+    let mut x = from.x;
+    let mut y = from.y;
+
+    let min_x = rect.min_x.max(0) as f32;
+    let min_y = rect.min_y.max(0) as f32;
+    let max_x = (rect.max_x as f32).min(viewport_size.x);
+    let max_y = (rect.max_y as f32).min(viewport_size.y);
 
-    for i in 0..steps as i32 {
-        let index = coords_to_index(x as usize, y as usize, SCREEN_WIDTH as usize);
-        buffer[index].store(color, std::sync::atomic::Ordering::Relaxed);
+    for _ in 0..steps as i32 {
+        if x >= min_x && y >= min_y && x < max_x && y < max_y {
+            let index = coords_to_index(x as usize, y as usize, viewport_size.x as usize);
+            buffer[index].store(color, std::sync::atomic::Ordering::Relaxed);
+        }
 
         x += x_inc;
         y += y_inc;